@@ -2,7 +2,7 @@ use log::{info, warn};
 use rand::Rng;
 use winit::event::{Event, KeyEvent, WindowEvent};
 use winit::keyboard::{KeyCode, PhysicalKey};
-use minirender::{Command, Renderer, Transform, UniformBufferType};
+use minirender::{BindSlot, Command, Renderer, Transform, TransformHandle};
 
 
 
@@ -27,34 +27,50 @@ fn main() {
         }
     };
 
+    // 4x MSAA where the adapter supports it (set_sample_count fails otherwise)
+    if renderer.get_supported_sample_counts().contains(&4) {
+        if let Err(e) = renderer.set_sample_count(4) {
+            eprintln!("Error enabling MSAA: {}", e);
+        }
+    }
 
     let mut render_node = renderer.get_render_node("Normal Cube".to_string());
     render_node.use_depth(true);
 
     render_node.add_command(
-        Command::LoadShader("examples/shaders/hello.wgsl".to_string())
+        Command::LoadShader("examples/shaders/cube.wgsl".to_string())
     );
 
-    let transform = Transform{
+    let mut camera = minirender::Camera::new([0.0, 1.0, 5.0].into(), [0.0, 0.0, 0.0].into(), 45.0, renderer.get_surface_configuration());
+    let camera_buffer = render_node.add_uniform_buffer(&camera, BindSlot::new(0, 0));
+
+    render_node.add_command(
+        Command::BindTexture(BindSlot::new(1, 0), "examples/textures/cube.jpeg".to_string())
+    );
+
+    // Two cubes in one node, each with its own transform
+    let spinning_cube = TransformHandle::new(Transform{
         position: [0.0, 0.0, -20.0].into(),
         rotation: [0.0, 0.0, 0.0].into(),
         scale: [1.0, 1.0, 1.0].into(),
-    };
-    let _transform_buffer = render_node.add_uniform_buffer(&transform, UniformBufferType::DYNAMIC);
-
-    let mut camera = minirender::Camera::new([0.0, 1.0, 5.0].into(), [0.0, 0.0, 0.0].into(), 45.0, renderer.get_surface_configuration());
-    let camera_buffer = render_node.add_uniform_buffer(&camera, UniformBufferType::DYNAMIC);
+    });
+    let still_cube = TransformHandle::new(Transform{
+        position: [4.0, 0.0, -20.0].into(),
+        rotation: [0.0, 0.0, 0.0].into(),
+        scale: [0.5, 0.5, 0.5].into(),
+    });
 
     render_node.add_command(
-        Command::BindTexture(1, "examples/textures/cube.jpeg".to_string())
+        Command::DrawMesh("examples/meshes/cube obj.obj".to_string(), Some(spinning_cube.clone()))
     );
-
     render_node.add_command(
-        Command::DrawMesh("examples/meshes/cube obj.obj".to_string())
+        Command::DrawMesh("examples/meshes/cube obj.obj".to_string(), Some(still_cube))
     );
 
 
-    renderer.add_render_node(render_node);
+    if let Err(e) = renderer.add_render_node(render_node) {
+        eprintln!("Error adding render node: {}", e);
+    }
 
 
 
@@ -63,10 +79,10 @@ fn main() {
     instanced_render_node.use_depth(true);
 
     instanced_render_node.add_command(
-        Command::LoadShader("examples/shaders/hello_inst.wgsl".to_string())
+        Command::LoadShader("examples/shaders/cube.wgsl".to_string())
     );
 
-    instanced_render_node.add_uniform_buffer_handle(camera_buffer.clone().unwrap(), UniformBufferType::DYNAMIC);
+    instanced_render_node.add_uniform_buffer_handle(&camera_buffer, BindSlot::new(0, 0));
     
     let mut transforms = Vec::new();
     // Generate a bunch of cubes in a grid
@@ -91,17 +107,22 @@ fn main() {
     }
 
     instanced_render_node.add_command(
-        Command::BindTexture(1, "examples/textures/instance.png".to_string())
+        Command::BindTexture(BindSlot::new(1, 0), "examples/textures/instance.png".to_string())
     );
 
     instanced_render_node.add_command(
         Command::DrawMeshInstanced("examples/meshes/cube obj.obj".to_string(), transforms)
     );
 
-    renderer.add_render_node(instanced_render_node);
+    if let Err(e) = renderer.add_render_node(instanced_render_node) {
+        eprintln!("Error adding render node: {}", e);
+    }
 
     // Once this is run, all the render nodes will be built and the pipeline will be created
-    renderer.initialize();
+    if let Err(e) = renderer.initialize() {
+        eprintln!("Error building render graph: {}", e);
+        return;
+    }
 
     event_loop
         .run(|event, target| {
@@ -116,9 +137,12 @@ fn main() {
                         // On RedrawRequested, request a redraw
                         WindowEvent::RedrawRequested => {
                             // Update buffers here
-                            if let Some(c_buffer) = &camera_buffer {
-                                c_buffer.update(&camera);
-                            }
+                            camera_buffer.update(&camera);
+                            renderer.update_sort_camera(&camera);
+
+                            let mut transform = spinning_cube.get();
+                            transform.rotation[1] += 0.01;
+                            spinning_cube.set(transform);
                         }
                         WindowEvent::Resized(new_size) => {
                             camera.resize(*new_size);
@@ -0,0 +1,93 @@
+// One source for both cube nodes - compiled with INSTANCED when the node draws
+// instances, TEXTURED when it binds a texture, and DRAW_TRANSFORM when its
+// DrawMesh commands carry their own transforms.
+#include "vertex.wgsl"
+#include "camera.wgsl"
+#include "transform.wgsl"
+#include "common.wgsl"
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec3<f32>,
+};
+
+#ifdef INSTANCED
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+#else
+#ifdef DRAW_TRANSFORM
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// Set per DrawMesh
+@group(2) @binding(0)
+var<uniform> transform: Transform;
+#else
+@group(0) @binding(0)
+var<uniform> transform: Transform;
+
+@group(0) @binding(1)
+var<uniform> camera: Camera;
+#endif
+#endif
+
+@vertex
+fn vert_main(
+    model: VertexInput,
+#ifdef INSTANCED
+    inst: InstanceInput,
+#endif
+) -> VertexOutput {
+    var out: VertexOutput;
+
+#ifdef INSTANCED
+    let model_matrix = instance_model_matrix(inst);
+
+    // use instance_index (built-in) to determine the color
+    out.color = vec3<f32>(sin(f32(inst.instance_index)), cos(f32(inst.instance_index)), 0.5);
+#else
+    let model_matrix = transform.model_matrix;
+    out.color = vec3<f32>(1.0, 1.0, 1.0);
+#endif
+
+    // Apply transformations
+    out.clip_position = camera.proj_view * model_matrix * vec4<f32>(model.position, 1.0);
+    out.normal = normalize((model_matrix * vec4<f32>(model.normal, 0.0)).xyz);
+    out.tex_coords = model.tex_coords;
+
+    return out;
+}
+
+#ifdef TEXTURED
+// Texture and sampler
+@group(1) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(1) @binding(1)
+var s_diffuse: sampler;
+#endif
+
+#ifndef DEPTH_ONLY
+@fragment
+fn frag_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Simple directional light
+    let light_dir = normalize(vec3<f32>(0.0, 0.5, 1.0));
+    let light_color = vec3<f32>(1.0, 1.0, 1.0);
+    let ambient_color = vec3<f32>(0.1, 0.1, 0.1);
+
+    // Lambertian shading
+    let normal = normalize(in.normal);
+    let light_intensity = max(dot(normal, light_dir), 0.0);
+
+    var final_color = (ambient_color + light_intensity * light_color) * in.color;
+
+#ifdef TEXTURED
+    // Apply texture
+    let tex_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    final_color *= tex_color.rgb;
+#endif
+
+    return vec4<f32>(aces_tonemapping(final_color), 1.0);
+}
+#endif
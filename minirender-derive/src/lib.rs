@@ -0,0 +1,361 @@
+//! Derive macros for minirender.
+//!
+//! `#[derive(Uniform)]` implements `minirender::Uniform` for any `encase::ShaderType`
+//! struct. Adding `#[uniform(wgsl)]` (or `#[uniform(wgsl = "Name")]` to rename it)
+//! also implements `minirender::WgslStruct`, which emits the matching WGSL struct.
+//! Registering a struct with the preprocessor registers the structs it includes too.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, GenericArgument, LitStr, PathArguments, Type};
+
+#[proc_macro_derive(Uniform, attributes(uniform))]
+pub fn derive_uniform(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let uniform = quote! {
+        impl #impl_generics ::minirender::Uniform for #name #ty_generics #where_clause {
+            fn to_wgpu(&self) -> ::std::vec::Vec<u8> {
+                let mut buffer = ::minirender::encase::UniformBuffer::new(::std::vec::Vec::new());
+                buffer.write(self).unwrap();
+                buffer.into_inner()
+            }
+        }
+    };
+
+    let wgsl = match struct_wgsl_name(input)? {
+        Some(wgsl_name) => expand_wgsl(input, &wgsl_name)?,
+        None => TokenStream2::new(),
+    };
+
+    Ok(quote! {
+        #uniform
+        #wgsl
+    })
+}
+
+// `#[uniform(wgsl)]` names the WGSL struct after the Rust one, `#[uniform(wgsl = "Name")]` renames it
+fn struct_wgsl_name(input: &DeriveInput) -> syn::Result<Option<String>> {
+    let mut wgsl_name = None;
+
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("uniform")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("wgsl") {
+                wgsl_name = Some(match meta.value() {
+                    Ok(value) => value.parse::<LitStr>()?.value(),
+                    Err(_) => input.ident.to_string(),
+                });
+                Ok(())
+            } else {
+                Err(meta.error("expected `wgsl` or `wgsl = \"Name\"`"))
+            }
+        })?;
+    }
+
+    Ok(wgsl_name)
+}
+
+fn expand_wgsl(input: &DeriveInput, wgsl_name: &str) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(input.generics.span(), "#[uniform(wgsl)] does not support generic structs"));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new(input.span(), "#[uniform(wgsl)] needs a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new(input.span(), "#[uniform(wgsl)] can only be used on structs")),
+    };
+
+    let mut includes = Vec::new();
+    let mut members = Vec::new();
+
+    for field in fields.iter() {
+        let field_name = field.ident.as_ref().unwrap().to_string();
+        let attributes = member_attributes(field)?;
+
+        let ty = match field_type_override(field)? {
+            Some(ty) => quote! { ::std::string::String::from(#ty) },
+            None => wgsl_type(&field.ty, &mut includes)?,
+        };
+
+        members.push(quote! {
+            source.push_str(&::std::format!("    {}{}: {},\n", #attributes, #field_name, #ty));
+        });
+    }
+
+    let header = format!("// Generated from `{}`.\n", name);
+
+    Ok(quote! {
+        impl ::minirender::WgslStruct for #name {
+            const WGSL_NAME: &'static str = #wgsl_name;
+
+            fn wgsl_struct() -> ::std::string::String {
+                let mut source = ::std::string::String::from(#header);
+                #(
+                    source.push_str(&::std::format!("#include \"{}.wgsl\"\n",
+                        <#includes as ::minirender::WgslStruct>::WGSL_NAME));
+                )*
+                source.push_str(&::std::format!("\nstruct {} {{\n", #wgsl_name));
+                #(#members)*
+                source.push_str("};\n");
+                source
+            }
+
+            fn register(preprocessor: &mut ::minirender::ShaderPreprocessor) {
+                preprocessor.add_source(::std::format!("{}.wgsl", #wgsl_name), <Self as ::minirender::WgslStruct>::wgsl_struct());
+                #(
+                    <#includes as ::minirender::WgslStruct>::register(preprocessor);
+                )*
+            }
+        }
+    })
+}
+
+// `#[uniform(wgsl = "type")]` on a field spells out its WGSL type
+fn field_type_override(field: &syn::Field) -> syn::Result<Option<String>> {
+    let mut ty = None;
+
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("uniform")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("wgsl") {
+                ty = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `wgsl = \"type\"`"))
+            }
+        })?;
+    }
+
+    Ok(ty)
+}
+
+// Carry encase's `#[align(N)]` and `#[size(N)]` over as `@align(N)` and `@size(N)`
+fn member_attributes(field: &syn::Field) -> syn::Result<String> {
+    let mut attributes = String::new();
+
+    for attr in field.attrs.iter() {
+        for name in ["align", "size"] {
+            if !attr.path().is_ident(name) {
+                continue;
+            }
+
+            let value: syn::Expr = attr.parse_args()?;
+            // `#[size(runtime)]` marks a runtime-sized array, which WGSL spells in the type
+            if let syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(value), .. }) = value {
+                attributes.push_str(&format!("@{}({}) ", name, value.base10_digits()));
+            }
+        }
+    }
+
+    Ok(attributes)
+}
+
+// An expression producing the WGSL spelling of a Rust type. Struct types are
+// looked up through their own `WgslStruct` impl and recorded in `includes`.
+fn wgsl_type(ty: &Type, includes: &mut Vec<Type>) -> syn::Result<TokenStream2> {
+    match ty {
+        Type::Array(array) => {
+            let element = wgsl_type(&array.elem, includes)?;
+            let len = &array.len;
+            Ok(quote! { ::std::format!("array<{}, {}>", #element, #len) })
+        }
+        Type::Path(path) if path.qself.is_none() => {
+            let segment = path.path.segments.last().unwrap();
+            let ident = segment.ident.to_string();
+
+            let scalar_arg = || -> syn::Result<String> {
+                match generic_arg(&segment.arguments) {
+                    Some(arg) => scalar(arg).ok_or_else(|| syn::Error::new(arg.span(), "expected f32, i32, u32 or f16")),
+                    None => Ok("f32".to_string()),
+                }
+            };
+
+            let wgsl = match ident.as_str() {
+                "f32" | "i32" | "u32" | "f16" => ident.clone(),
+                "ArrayLength" => "u32".to_string(),
+
+                // nalgebra
+                "Vector2" | "Vector3" | "Vector4" | "Point2" | "Point3" | "Point4" => {
+                    format!("vec{}<{}>", &ident[ident.len() - 1..], scalar_arg()?)
+                }
+                "Matrix2" | "Matrix3" | "Matrix4" => {
+                    let n = &ident[ident.len() - 1..];
+                    format!("mat{}x{}<{}>", n, n, scalar_arg()?)
+                }
+                // nalgebra's MatrixRxC is R rows by C columns, WGSL's matCxR is C columns by R rows
+                _ if ident.len() == 9 && ident.starts_with("Matrix") && ident.as_bytes()[7] == b'x' => {
+                    let rows = &ident[6..7];
+                    let columns = &ident[8..9];
+                    format!("mat{}x{}<{}>", columns, rows, scalar_arg()?)
+                }
+
+                // glam / mint style names
+                "Vec2" | "Vec3" | "Vec4" | "Vec3A" => format!("vec{}<f32>", &ident[3..4]),
+                "IVec2" | "IVec3" | "IVec4" => format!("vec{}<i32>", &ident[4..5]),
+                "UVec2" | "UVec3" | "UVec4" => format!("vec{}<u32>", &ident[4..5]),
+                "Mat2" | "Mat3" | "Mat4" => {
+                    let n = &ident[3..4];
+                    format!("mat{}x{}<f32>", n, n)
+                }
+
+                // A runtime-sized array
+                "Vec" => {
+                    let element = match generic_arg(&segment.arguments) {
+                        Some(element) => wgsl_type(element, includes)?,
+                        None => return Err(syn::Error::new(ty.span(), "expected Vec<T>")),
+                    };
+                    return Ok(quote! { ::std::format!("array<{}>", #element) });
+                }
+
+                // Anything else has to be another struct with a WGSL declaration
+                _ => {
+                    includes.push(ty.clone());
+                    return Ok(quote_spanned! { ty.span() =>
+                        ::std::string::String::from(<#ty as ::minirender::WgslStruct>::WGSL_NAME)
+                    });
+                }
+            };
+
+            Ok(quote! { ::std::string::String::from(#wgsl) })
+        }
+        _ => Err(syn::Error::new(ty.span(), "no WGSL equivalent for this type, use #[uniform(wgsl = \"type\")]")),
+    }
+}
+
+fn generic_arg(arguments: &PathArguments) -> Option<&Type> {
+    match arguments {
+        PathArguments::AngleBracketed(arguments) => arguments.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+fn scalar(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => {
+            let ident = path.path.segments.last()?.ident.to_string();
+            matches!(ident.as_str(), "f32" | "i32" | "u32" | "f16").then_some(ident)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(input: DeriveInput) -> syn::Result<String> {
+        expand(&input).map(|tokens| tokens.to_string())
+    }
+
+    fn error(input: DeriveInput) -> String {
+        expand(&input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn plain_derive_only_implements_uniform() {
+        let expanded = expand_str(syn::parse_quote! {
+            struct Light { color: Vector3<f32> }
+        }).unwrap();
+
+        assert!(expanded.contains(":: minirender :: Uniform for Light"));
+        assert!(!expanded.contains("WgslStruct"));
+    }
+
+    #[test]
+    fn wgsl_attribute_names_the_struct() {
+        let expanded = expand_str(syn::parse_quote! {
+            #[uniform(wgsl)]
+            struct Light { color: Vector3<f32> }
+        }).unwrap();
+        assert!(expanded.contains("const WGSL_NAME : & 'static str = \"Light\""));
+
+        let expanded = expand_str(syn::parse_quote! {
+            #[uniform(wgsl = "PointLight")]
+            struct Light { color: Vector3<f32> }
+        }).unwrap();
+        assert!(expanded.contains("const WGSL_NAME : & 'static str = \"PointLight\""));
+    }
+
+    #[test]
+    fn field_types_are_translated() {
+        let expanded = expand_str(syn::parse_quote! {
+            #[uniform(wgsl)]
+            struct Params {
+                position: Point3<f32>,
+                rotation: Matrix3x4<f32>,
+                #[align(16)]
+                count: u32,
+                #[uniform(wgsl = "atomic<u32>")]
+                counter: u32,
+                weights: [f32; 4],
+            }
+        }).unwrap();
+
+        for expected in ["\"vec3<f32>\"", "\"mat4x3<f32>\"", "\"@align(16) \"", "\"atomic<u32>\"", "\"array<{}, {}>\""] {
+            assert!(expanded.contains(expected), "{} missing from {}", expected, expanded);
+        }
+    }
+
+    #[test]
+    fn nested_structs_are_included_and_registered() {
+        let expanded = expand_str(syn::parse_quote! {
+            #[uniform(wgsl)]
+            struct Scene { lights: [Light; 4] }
+        }).unwrap();
+
+        assert!(expanded.contains("< Light as :: minirender :: WgslStruct > :: WGSL_NAME"));
+        assert!(expanded.contains("< Light as :: minirender :: WgslStruct > :: register (preprocessor)"));
+    }
+
+    #[test]
+    fn unsupported_input_is_an_error() {
+        assert!(error(syn::parse_quote! {
+            #[uniform(wgsl)]
+            struct Wrapper<T> { value: T }
+        }).contains("generic"));
+
+        assert!(error(syn::parse_quote! {
+            #[uniform(wgsl)]
+            struct Pair(f32, f32);
+        }).contains("named fields"));
+
+        assert!(error(syn::parse_quote! {
+            #[uniform(wgsl)]
+            enum Mode { A, B }
+        }).contains("only be used on structs"));
+
+        assert!(error(syn::parse_quote! {
+            #[uniform(glsl)]
+            struct Light { color: Vector3<f32> }
+        }).contains("expected `wgsl`"));
+
+        assert!(error(syn::parse_quote! {
+            #[uniform(wgsl)]
+            struct Light { color: (f32, f32) }
+        }).contains("no WGSL equivalent"));
+
+        assert!(error(syn::parse_quote! {
+            #[uniform(wgsl)]
+            struct Light { color: Vector3<f64> }
+        }).contains("expected f32"));
+    }
+}
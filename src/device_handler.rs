@@ -21,16 +21,31 @@ impl DeviceHandler {
             panic!("Failed to request adapter");
         });
 
+        // Push constants and multi-draw are optional - render nodes fall back to a small
+        // uniform and to separate indirect draws without them. Without vertex writable
+        // storage, read-write storage buffers are only visible to fragment shaders.
+        // Adapter-specific format features unlock MSAA sample counts other than 1 and 4.
+        let required_features = adapter.features() & (wgpu::Features::PUSH_CONSTANTS
+            | wgpu::Features::MULTI_DRAW_INDIRECT
+            | wgpu::Features::VERTEX_WRITABLE_STORAGE
+            | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            | wgpu::Features::DEPTH32FLOAT_STENCIL8);
+
+        let mut required_limits = if cfg!(target_arch = "wasm32") {
+            wgpu::Limits::downlevel_webgl2_defaults()
+        } else {
+            wgpu::Limits::default()
+        };
+        if required_features.contains(wgpu::Features::PUSH_CONSTANTS) {
+            required_limits.max_push_constant_size = adapter.limits().max_push_constant_size;
+        }
+
         let (device, queue) = pollster::block_on(async {
             adapter
                 .request_device(
                     &wgpu::DeviceDescriptor {
-                        required_features: wgpu::Features::empty(),
-                        required_limits: if cfg!(target_arch = "wasm32") {
-                            wgpu::Limits::downlevel_webgl2_defaults()
-                        } else {
-                            wgpu::Limits::default()
-                        },
+                        required_features,
+                        required_limits,
                         label: Some("Device"),
                     },
                     None,
@@ -61,6 +76,38 @@ impl DeviceHandler {
         self.queue.clone()
     }
 
+    // The depth formats the renderer can use on this device
+    pub fn get_depth_formats(&self) -> Vec<wgpu::TextureFormat> {
+        let mut formats = vec![wgpu::TextureFormat::Depth32Float, wgpu::TextureFormat::Depth24PlusStencil8];
+        if self.device.features().contains(wgpu::Features::DEPTH32FLOAT_STENCIL8) {
+            formats.push(wgpu::TextureFormat::Depth32FloatStencil8);
+        }
+
+        formats
+    }
+
+    // The MSAA sample counts that every color format can render and resolve at, with
+    // `depth_format` (if any) as the depth attachment
+    pub fn get_supported_sample_counts(&self, color_formats: &[wgpu::TextureFormat], depth_format: Option<wgpu::TextureFormat>) -> Vec<u32> {
+        let features = self.device.features();
+
+        let format_features = |format: wgpu::TextureFormat| {
+            // Without the feature the device only allows what WebGPU guarantees
+            if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+                self.adapter.get_texture_format_features(format).flags
+            } else {
+                format.guaranteed_format_features(features).flags
+            }
+        };
+
+        [1, 2, 4, 8].into_iter().filter(|&count| {
+            count == 1 || (color_formats.iter().all(|format| {
+                let color = format_features(*format);
+                color.sample_count_supported(count) && color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+            }) && depth_format.is_none_or(|format| format_features(format).sample_count_supported(count)))
+        }).collect()
+    }
+
     pub fn begin_command_buffer(&self, label: Option<&'static str>) -> wgpu::CommandEncoder {
         self.device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
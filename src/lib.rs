@@ -5,13 +5,30 @@ mod renderer;
 mod surface_wrapper;
 mod types;
 mod pipeline;
+mod shader;
+
+// Lets the derive macros name `::minirender` from inside this crate too
+extern crate self as minirender;
 
 pub use renderer::Renderer;
 
-pub use render_graph::Command;
+pub use render_graph::{BuildError, BuildReport, Command, NodeId, RenderNode, RunPolicy};
+pub use render_graph::{RenderGraphTemplate, SubgraphBuilder, SubgraphParameters};
+pub use render_graph::{TransientMemoryReport, TransientSize, TransientTextureDescriptor};
+pub use render_graph::{CommandDescription, DataDescription, GraphDescription, NodeDescription, NodeSettings, PushConstantRangeDescription, ShaderStageDescription,
+                       StencilDescription, TransformDescription, UniformArenaDescription, UniformDescription};
 pub use types::Camera;
-pub use types::Transform;
-pub use types::UniformBufferType;
+pub use types::{Transform, TransformHandle};
+pub use types::BindSlot;
+pub use types::{DrawIndexedIndirectArgs, DrawIndirectArgs, IndirectArgs, StorageBuffer};
+pub use types::{CameraUniform, TransformUniform, Uniform, UniformBuffer};
+pub use minirender_derive::Uniform;
+pub use shader::{BindingKind, BindingMismatch, DiagnosticLabel, ReflectedBinding, ShaderDiagnostic,
+                 ShaderPermutation, ShaderPreprocessor, ShaderReflection, WgslStruct};
+
+// Used by `#[derive(Uniform)]`
+#[doc(hidden)]
+pub use encase;
 
 use std::ops::Deref;
 use std::ops::DerefMut;
@@ -1,11 +1,19 @@
 use std::num::NonZeroU32;
 use crate::Handle;
+use crate::render_graph::OitTargets;
 
 pub struct PipelineSettings{
     primitive_mode: wgpu::PrimitiveState,
-    depth_stencil: Option<wgpu::DepthStencilState>,
+    depth_test: bool,
     multisample: wgpu::MultisampleState,
     multiview: Option<NonZeroU32>,
+    depth_only: bool,
+    push_constant_ranges: Vec<wgpu::PushConstantRange>,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    stencil: Option<wgpu::StencilState>,
+    transparent: bool,
+    oit: bool,
 }
 
 impl Default for PipelineSettings{
@@ -21,7 +29,7 @@ impl Default for PipelineSettings{
                 conservative: false,
             },
 
-            depth_stencil: None,
+            depth_test: false,
 
             multisample: wgpu::MultisampleState{
                 count: 1,
@@ -29,22 +37,84 @@ impl Default for PipelineSettings{
                 alpha_to_coverage_enabled: false,
             },
 
-            multiview: None
+            multiview: None,
+            depth_only: false,
+            push_constant_ranges: Vec::new(),
+            color_format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            depth_format: wgpu::TextureFormat::Depth32Float,
+            stencil: None,
+            transparent: false,
+            oit: false,
         }
     }
 }
 
 impl PipelineSettings{
+    // Test depth against the depth_format attachment, and write it unless the
+    // pipeline is transparent or OIT
     pub fn enable_depth_stencil(mut self) -> Self {
-        self.depth_stencil = Some(
-            wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }
-        );
+        self.depth_test = true;
+
+        self
+    }
+
+    // Depth-only pipelines have no fragment stage and no color target,
+    // e.g. for shadow maps or a depth pre-pass.
+    pub fn enable_depth_only(mut self) -> Self {
+        self = self.enable_depth_stencil();
+        self.depth_only = true;
+
+        self
+    }
+
+    // Must match the target the node renders to, usually the surface format
+    pub fn color_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.color_format = format;
+
+        self
+    }
+
+    // Must match the node's depth texture. Used for both the depth and stencil tests.
+    pub fn depth_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.depth_format = format;
+
+        self
+    }
+
+    // Needs a depth format with a stencil aspect. Without depth testing enabled, the
+    // depth/stencil attachment is only used for the stencil test.
+    pub fn stencil(mut self, stencil: wgpu::StencilState) -> Self {
+        self.stencil = Some(stencil);
+
+        self
+    }
+
+    // Alpha blending, and depth is tested but not written so the draws behind
+    // (drawn first, if sorted back to front) still show through
+    pub fn enable_transparency(mut self) -> Self {
+        self.transparent = true;
+
+        self
+    }
+
+    // Render into the OIT accumulation and revealage targets instead of the color
+    // target, without writing depth
+    pub fn enable_oit(mut self) -> Self {
+        self.oit = true;
+
+        self
+    }
+
+    // MSAA - the node's color and depth attachments must have the same sample count
+    pub fn sample_count(mut self, count: u32) -> Self {
+        self.multisample.count = count;
+
+        self
+    }
+
+    // Needs Features::PUSH_CONSTANTS on the device
+    pub fn push_constant_ranges(mut self, ranges: Vec<wgpu::PushConstantRange>) -> Self {
+        self.push_constant_ranges = ranges;
 
         self
     }
@@ -55,61 +125,91 @@ pub struct Pipeline{
 }
 
 impl Pipeline{
-    pub fn new(device: Handle<wgpu::Device>, shader: wgpu::ShaderModule,
+    pub fn new(device: Handle<wgpu::Device>, shader: &wgpu::ShaderModule,
                bind_group_layouts: Vec<&wgpu::BindGroupLayout>,
-               vertex_buffer_layouts: Vec<wgpu::VertexBufferLayout>, use_depth: bool) -> Self{
-        let layout = Self::create_layout(device.clone(), bind_group_layouts);
+               vertex_buffer_layouts: Vec<wgpu::VertexBufferLayout>, pipeline_settings: PipelineSettings) -> Result<Self, wgpu::Error>{
+        // Catch validation errors here instead of letting them panic the device
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let layout = Self::create_layout(device.clone(), bind_group_layouts, &pipeline_settings.push_constant_ranges);
 
         let pipeline = Self::create_pipeline(device.clone(), layout, shader,
-                                             vertex_buffer_layouts, use_depth);
+                                             vertex_buffer_layouts, pipeline_settings);
 
-        Self{
-            pipeline
+        if let Some(error) = pollster::block_on(device.pop_error_scope()){
+            return Err(error);
         }
+
+        Ok(Self{
+            pipeline
+        })
     }
 
     pub fn bind_pipeline<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>){
         render_pass.set_pipeline(&self.pipeline);
     }
 
-    fn create_layout(device: Handle<wgpu::Device>, bind_group_layouts: Vec<&wgpu::BindGroupLayout>) -> wgpu::PipelineLayout{
+    fn create_layout(device: Handle<wgpu::Device>, bind_group_layouts: Vec<&wgpu::BindGroupLayout>,
+                     push_constant_ranges: &[wgpu::PushConstantRange]) -> wgpu::PipelineLayout{
         device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor{
             label: Some("Pipeline Layout"),
             bind_group_layouts: &bind_group_layouts,
-            push_constant_ranges: &[],
+            push_constant_ranges,
         })
     }
 
     fn create_pipeline(device: Handle<wgpu::Device>, layout: wgpu::PipelineLayout,
-                       shader: wgpu::ShaderModule,
+                       shader: &wgpu::ShaderModule,
                        vertex_buffer_layouts: Vec<wgpu::VertexBufferLayout>,
-                       use_depth: bool) -> wgpu::RenderPipeline{
-
-        let mut pipeline_settings = PipelineSettings::default();
-
-        if use_depth{
-            pipeline_settings = pipeline_settings.enable_depth_stencil();
-        }
+                       pipeline_settings: PipelineSettings) -> wgpu::RenderPipeline{
+        let depth_stencil = match (pipeline_settings.depth_test, pipeline_settings.stencil) {
+            (true, stencil) => Some(wgpu::DepthStencilState{
+                format: pipeline_settings.depth_format,
+                depth_write_enabled: !pipeline_settings.transparent && !pipeline_settings.oit,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: stencil.unwrap_or_default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            (false, Some(stencil)) => Some(wgpu::DepthStencilState{
+                format: pipeline_settings.depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil,
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            (false, None) => None,
+        };
+
+        let oit_targets = OitTargets::color_target_states();
+        let color_targets = [Some(wgpu::ColorTargetState{
+            format: pipeline_settings.color_format,
+            blend: Some(if pipeline_settings.transparent {
+                wgpu::BlendState::ALPHA_BLENDING
+            } else {
+                wgpu::BlendState::REPLACE
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
 
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor{
             label: Some("Render Pipeline"),
             layout: Some(&layout),
             vertex: wgpu::VertexState{
-                module: &shader,
+                module: shader,
                 entry_point: "vert_main",
                 buffers: &vertex_buffer_layouts,
             },
-            fragment: Some(wgpu::FragmentState{
-                module: &shader,
-                entry_point: "frag_main",
-                targets: &[Some(wgpu::ColorTargetState{
-                    format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
+            fragment: if pipeline_settings.depth_only {
+                None
+            } else {
+                Some(wgpu::FragmentState{
+                    module: shader,
+                    entry_point: "frag_main",
+                    targets: if pipeline_settings.oit { &oit_targets } else { &color_targets },
+                })
+            },
             primitive: pipeline_settings.primitive_mode,
-            depth_stencil: pipeline_settings.depth_stencil,
+            depth_stencil,
             multisample: pipeline_settings.multisample,
             multiview: pipeline_settings.multiview,
         })
@@ -0,0 +1,148 @@
+use std::fmt;
+
+use crate::render_graph::NodeId;
+use crate::shader::{BindingMismatch, ShaderDiagnostic};
+
+/// Something that stopped a render node from building its pipeline.
+#[derive(Debug)]
+pub enum BuildError{
+    // The node has no LoadShader command
+    MissingShader,
+    // The shader couldn't be read or a preprocessor directive was invalid
+    Preprocess(String),
+    // The shader failed to parse or validate
+    Shader(ShaderDiagnostic),
+    // The node's resources don't line up with the shader's bindings
+    Binding(BindingMismatch),
+    // Two of the node's resource sets were placed in the same bind group
+    GroupConflict{ group: u32, first: String, second: String },
+    // BindStorageBuffer names a buffer that was never created
+    MissingStorageBuffer(String),
+    // The node renders to or samples a transient texture that was never declared
+    MissingTransient(String),
+    // A transient texture used in a way it can't be
+    Transient(String),
+    // The node pushes per-draw uniforms but has no uniform arena to put them in
+    MissingUniformArena,
+    // A per-draw uniform is bigger than the node's arena blocks
+    UniformBlockTooLarge{ size: u64, block_size: u64 },
+    // The node draws a mesh but was told not to use the Vertex layout
+    MeshWithoutVertexLayout(String),
+    // The node draws without a mesh but still uses the Vertex layout, so there's no vertex buffer to bind
+    VertexLayoutWithoutMesh,
+    // A viewport or scissor rect with no area, or a depth range outside 0..1
+    Viewport(String),
+    // An indirect draw's offset is misaligned or its args run past the end of the buffer
+    IndirectArgs(String),
+    // Push constant ranges or SetPushConstants commands that can't work
+    PushConstants(String),
+    // The MSAA sample count isn't one the adapter can render and resolve at
+    UnsupportedSampleCount{ count: u32, supported: Vec<u32> },
+    // The node draws to the frame at a different sample count than the nodes before it
+    MixedSampleCounts{ count: u32, frame: u32 },
+    // The depth format isn't one the renderer supports on this device
+    UnsupportedDepthFormat(wgpu::TextureFormat),
+    // The node sets a stencil state but the depth format has no stencil aspect
+    NoStencilAspect(wgpu::TextureFormat),
+    // wgpu rejected the pipeline
+    Pipeline(String),
+    // A graph edit named a node that isn't in the graph
+    UnknownNode(NodeId),
+    // A subgraph edit named a render node
+    NotASubgraph(NodeId),
+}
+
+impl fmt::Display for BuildError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            BuildError::MissingShader => write!(f, "no LoadShader command"),
+            BuildError::Preprocess(message) => write!(f, "{}", message),
+            BuildError::Shader(diagnostic) => write!(f, "{}", diagnostic),
+            BuildError::Binding(mismatch) => write!(f, "{}", mismatch),
+            BuildError::GroupConflict{ group, first, second } => {
+                write!(f, "@group({}) is used by both {} and {}", group, first, second)
+            }
+            BuildError::MissingStorageBuffer(name) => write!(f, "no storage buffer named {}", name),
+            BuildError::MissingTransient(name) => {
+                write!(f, "no transient texture named {} (see Renderer::declare_transient_texture)", name)
+            }
+            BuildError::Transient(message) => write!(f, "transient texture: {}", message),
+            BuildError::MissingUniformArena => write!(f, "PushUniform used without a uniform arena (see RenderNode::add_uniform_arena)"),
+            BuildError::UniformBlockTooLarge{ size, block_size } => {
+                write!(f, "pushed uniform is {} bytes but the uniform arena holds {} byte blocks", size, block_size)
+            }
+            BuildError::MeshWithoutVertexLayout(mesh) => {
+                write!(f, "mesh {} is drawn but the node has use_vertex_layout(false)", mesh)
+            }
+            BuildError::VertexLayoutWithoutMesh => {
+                write!(f, "a draw without a mesh needs the node to have use_vertex_layout(false)")
+            }
+            BuildError::Viewport(message) => write!(f, "viewport: {}", message),
+            BuildError::IndirectArgs(message) => write!(f, "indirect args: {}", message),
+            BuildError::PushConstants(message) => write!(f, "push constants: {}", message),
+            BuildError::UnsupportedSampleCount{ count, supported } => {
+                write!(f, "sample count {} is not supported (supported: {:?})", count, supported)
+            }
+            BuildError::MixedSampleCounts{ count, frame } => {
+                write!(f, "the node renders at {}x MSAA but earlier nodes draw to the frame at {}x", count, frame)
+            }
+            BuildError::UnsupportedDepthFormat(format) => write!(f, "depth format {:?} is not supported", format),
+            BuildError::NoStencilAspect(format) => {
+                write!(f, "a stencil state is set but the depth format {:?} has no stencil (see Renderer::set_depth_format)", format)
+            }
+            BuildError::Pipeline(message) => write!(f, "pipeline creation failed: {}", message),
+            BuildError::UnknownNode(id) => write!(f, "no render node with {:?} in the graph", id),
+            BuildError::NotASubgraph(id) => write!(f, "{:?} is a render node, not a template instance", id),
+        }
+    }
+}
+
+impl std::error::Error for BuildError{}
+
+/// Every failure from building a render graph, grouped by node.
+#[derive(Debug, Default)]
+pub struct BuildReport{
+    pub failures: Vec<(String, Vec<BuildError>)>,
+}
+
+impl BuildReport{
+    pub fn add(&mut self, node: &str, errors: Vec<BuildError>){
+        self.failures.push((node.to_string(), errors));
+    }
+
+    // The report for an edit that failed before any node was built
+    pub(crate) fn node_error(id: NodeId, error: BuildError) -> Self{
+        let mut report = Self::default();
+        report.add(&format!("{:?}", id), vec![error]);
+        report
+    }
+
+    // The report for an edit to a node that isn't in the graph
+    pub(crate) fn unknown_node(id: NodeId) -> Self{
+        Self::node_error(id, BuildError::UnknownNode(id))
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for BuildReport{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        writeln!(f, "Failed to build {} render node(s)", self.failures.len())?;
+
+        for (node, errors) in self.failures.iter(){
+            writeln!(f, "\nrender node \"{}\":", node)?;
+            for error in errors.iter(){
+                // Indent multi-line diagnostics under their node
+                for line in error.to_string().trim_end().lines(){
+                    writeln!(f, "  {}", line)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildReport{}
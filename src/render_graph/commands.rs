@@ -1,27 +1,105 @@
+use std::ops::Range;
+
 use crate::render_graph::ResourceHandle;
 use crate::types;
+use crate::types::{BindSlot, RawStorageBuffer};
+use crate::Handle;
 
 pub enum Command{
     LoadShader(String),
+    // Add a define to the shader variant this node compiles (INSTANCED, TEXTURED,
+    // DEPTH_ONLY, PUSH_CONSTANTS and DRAW_TRANSFORM are added automatically from the node's commands and settings)
+    Define(String),
 
     // Draw commands
 
-    // Bind a texture to a texture unit (will also load the texture if it's not loaded)
-    BindTexture(u32, String),
+    // Bind a texture (will also load the texture if it's not loaded). The view goes at
+    // the slot's binding and the sampler at the binding after it.
+    BindTexture(BindSlot, String),
+
+    // Bind a transient texture an earlier node rendered into (see
+    // RenderNode::render_to_transient), view then sampler like BindTexture
+    BindTransient(BindSlot, String),
+
+    // Bind a storage buffer created with Renderer::create_storage_buffer (or
+    // create_indirect_buffer), by name
+    BindStorageBuffer(BindSlot, String),
+
+    // Set push constant bytes for the following draws: (stages, offset, data). The
+    // node must declare a range covering them with add_push_constant_range.
+    SetPushConstants(wgpu::ShaderStages, u32, Vec<u8>),
+
+    // Write a block of per-draw data into the node's uniform arena. Draws after
+    // this see it, until the next PushUniform. The block is read every frame but
+    // only uploaded when it changes, so pushing a MutHandle lets the data be
+    // updated through the handle without a rebuild.
+    PushUniform(Box<dyn types::Uniform>),
 
-    // Draw a mesh (will also load the mesh if it's not loaded)
-    DrawMesh(String),
+    // Draw a mesh (will also load the mesh if it's not loaded). With a transform, its
+    // model matrix is given to the shader per draw at the node's draw transform slot
+    // (see RenderNode::set_draw_transform_slot) and the shader is compiled with DRAW_TRANSFORM.
+    DrawMesh(String, Option<types::TransformHandle>),
     // Draw an instanced mesh (will also load the mesh if it's not loaded)
     DrawMeshInstanced(String, Vec<types::Transform>),
+
+    // Draw without a mesh - the vertex shader makes its own geometry from the
+    // vertex/instance index (fullscreen triangles, vertex pulling). Use it in a node
+    // with use_vertex_layout(false) so the pipeline expects no vertex buffers.
+    Draw{ vertices: Range<u32>, instances: Range<u32> },
+
+    // Restrict the draws that follow to part of the target, in pixels from the
+    // top left. Both need a non-empty rect (and the depth range must be within 0..1),
+    // are clamped to the target, and reset at the start of each node.
+    SetViewport{ x: f32, y: f32, width: f32, height: f32, min_depth: f32, max_depth: f32 },
+    SetScissor{ x: u32, y: u32, width: u32, height: u32 },
+    // The reference value the node's stencil state compares against and can write
+    SetStencilReference(u32),
+
+    // Indirect draws, with arguments read from a storage buffer (by name) at a byte
+    // offset - see DrawIndirectArgs and DrawIndexedIndirectArgs. A compute pass can fill the
+    // buffer so draw counts never touch the CPU.
+
+    // A mesh-less draw, like Command::Draw
+    DrawIndirect{ buffer: String, offset: u64 },
+    // Draw a mesh with one DrawIndexedIndirectArgs per submesh
+    DrawIndexedIndirect{ mesh: String, buffer: String, offset: u64 },
+    // Draw a mesh `count` times per submesh, with multi_draw_indexed_indirect when
+    // the device has Features::MULTI_DRAW_INDIRECT
+    MultiDrawIndexedIndirect{ mesh: String, buffer: String, offset: u64, count: u32 },
 }
 
 pub enum DrawCommand {
     // These are the commands we use to execute the render graph
     // They have the ResourceID of the resource they are using,
     // and any other relevant data
-    BindTexture(u32, ResourceHandle),
+    BindTexture(u32, ResourceHandle), // Bind group index, texture
+
+    BindTransient(usize), // Index of the node's transient reads
+
+    SetUniformBlock(usize), // Index of the block in the node's uniform arena
+
+    SetPushConstants(usize), // Index of the node's push constant data
+
+    SetDrawTransform(usize), // Index of the node's per-draw transforms
 
     DrawMesh(ResourceHandle),
 
-    DrawMeshInstanced(ResourceHandle),
+    DrawMeshInstanced(ResourceHandle, usize), // Mesh, index of the node's instance buffers
+
+    Draw(Range<u32>, Range<u32>), // Vertices, instances
+
+    SetViewport([f32; 4], f32, f32), // x, y, width, height, min depth, max depth
+    SetScissor([u32; 4]), // x, y, width, height
+    SetStencilReference(u32),
+
+    DrawIndirect(Handle<RawStorageBuffer>, u64), // Args buffer, offset
+
+    DrawIndexedIndirect(ResourceHandle, Handle<RawStorageBuffer>, u64, u32), // Mesh, args buffer, offset, draws per submesh
+}
+
+impl DrawCommand {
+    pub fn is_draw(&self) -> bool {
+        matches!(self, DrawCommand::DrawMesh(_) | DrawCommand::DrawMeshInstanced(..) | DrawCommand::Draw(..)
+            | DrawCommand::DrawIndirect(..) | DrawCommand::DrawIndexedIndirect(..))
+    }
 }
\ No newline at end of file
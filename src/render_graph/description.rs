@@ -0,0 +1,548 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::render_graph::{Command, RenderNode, ResourceManager, RunPolicy};
+use crate::types::{BindSlot, Transform, TransformHandle, Uniform};
+use crate::Handle;
+
+/// A render graph written down as data, loaded from a `.ron` or `.json` file with
+/// `Renderer::load_render_graph`. Uniform buffers and transforms the file uses by
+/// name are registered from code with `Renderer::register_uniform_buffer` and
+/// `Renderer::register_transform`.
+///
+/// Push constants and PushUniform blocks are written as plain values (see
+/// DataDescription), so they're fixed once loaded. Data that changes every frame
+/// belongs in a registered uniform buffer or transform instead.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GraphDescription{
+    pub nodes: Vec<NodeDescription>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeDescription{
+    pub name: String,
+    pub shader: String,
+    #[serde(default)]
+    pub defines: Vec<String>,
+    #[serde(default)]
+    pub settings: NodeSettings,
+    // Registered uniform buffers, by name
+    #[serde(default)]
+    pub uniforms: Vec<UniformDescription>,
+    #[serde(default)]
+    pub commands: Vec<CommandDescription>,
+}
+
+// Pipeline settings - the RenderNode setters of the same names
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeSettings{
+    // Written as a plain bool - left out, transparent and OIT nodes test depth and others don't
+    #[serde(deserialize_with = "deserialize_set_bool", serialize_with = "serialize_set_bool", skip_serializing_if = "Option::is_none")]
+    pub use_depth: Option<bool>,
+    pub depth_only: bool,
+    pub use_vertex_layout: bool,
+    pub transparent: bool,
+    pub sort_front_to_back: bool,
+    pub order_independent_transparency: bool,
+    pub sample_count: Option<u32>,
+    pub clear_color: Option<[f64; 4]>,
+    pub clear_depth: Option<f32>,
+    pub clear_stencil: Option<u32>,
+    pub draw_transform_slot: Option<BindSlot>,
+    pub stencil_state: Option<StencilDescription>,
+    pub push_constant_ranges: Vec<PushConstantRangeDescription>,
+    pub push_constant_fallback_slot: Option<BindSlot>,
+    pub uniform_arena: Option<UniformArenaDescription>,
+    // Render into this transient texture instead of the frame
+    pub transient_target: Option<String>,
+    pub run_policy: RunPolicy,
+}
+
+impl Default for NodeSettings{
+    fn default() -> Self{
+        Self{
+            use_depth: None,
+            depth_only: false,
+            use_vertex_layout: true,
+            transparent: false,
+            sort_front_to_back: false,
+            order_independent_transparency: false,
+            sample_count: None,
+            clear_color: None,
+            clear_depth: None,
+            clear_stencil: None,
+            draw_transform_slot: None,
+            stencil_state: None,
+            push_constant_ranges: Vec::new(),
+            push_constant_fallback_slot: None,
+            uniform_arena: None,
+            transient_target: None,
+            run_policy: RunPolicy::Always,
+        }
+    }
+}
+
+fn deserialize_set_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error>{
+    bool::deserialize(deserializer).map(Some)
+}
+
+fn serialize_set_bool<S: Serializer>(value: &Option<bool>, serializer: S) -> Result<S::Ok, S::Error>{
+    serializer.serialize_bool(value.unwrap_or_default())
+}
+
+// wgpu's stencil state, written out field for field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct StencilDescription(#[serde(with = "StencilStateDef")] pub wgpu::StencilState);
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "wgpu::StencilState")]
+struct StencilStateDef{
+    #[serde(with = "StencilFaceStateDef", default)]
+    front: wgpu::StencilFaceState,
+    #[serde(with = "StencilFaceStateDef", default)]
+    back: wgpu::StencilFaceState,
+    #[serde(default = "full_stencil_mask")]
+    read_mask: u32,
+    #[serde(default = "full_stencil_mask")]
+    write_mask: u32,
+}
+
+fn full_stencil_mask() -> u32{
+    0xff
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "wgpu::StencilFaceState")]
+struct StencilFaceStateDef{
+    #[serde(with = "CompareFunctionDef")]
+    compare: wgpu::CompareFunction,
+    #[serde(with = "StencilOperationDef", default)]
+    fail_op: wgpu::StencilOperation,
+    #[serde(with = "StencilOperationDef", default)]
+    depth_fail_op: wgpu::StencilOperation,
+    #[serde(with = "StencilOperationDef", default)]
+    pass_op: wgpu::StencilOperation,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "wgpu::CompareFunction")]
+enum CompareFunctionDef{
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "wgpu::StencilOperation")]
+enum StencilOperationDef{
+    Keep,
+    Zero,
+    Replace,
+    Invert,
+    IncrementClamp,
+    DecrementClamp,
+    IncrementWrap,
+    DecrementWrap,
+}
+
+// A push constant range (RenderNode::add_push_constant_range)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushConstantRangeDescription{
+    pub stages: Vec<ShaderStageDescription>,
+    pub range: (u32, u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShaderStageDescription{
+    Vertex,
+    Fragment,
+}
+
+impl PushConstantRangeDescription{
+    fn to_stages(&self) -> wgpu::ShaderStages{
+        shader_stages(&self.stages)
+    }
+}
+
+fn shader_stages(stages: &[ShaderStageDescription]) -> wgpu::ShaderStages{
+    stages.iter().fold(wgpu::ShaderStages::NONE, |stages, stage| stages | match stage {
+        ShaderStageDescription::Vertex => wgpu::ShaderStages::VERTEX,
+        ShaderStageDescription::Fragment => wgpu::ShaderStages::FRAGMENT,
+    })
+}
+
+// Push constant or uniform block bytes, written as the values the shader reads.
+// They're copied as they are, so any padding the WGSL layout needs must be written out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DataDescription{
+    Bytes(Vec<u8>),
+    F32(Vec<f32>),
+    U32(Vec<u32>),
+    I32(Vec<i32>),
+}
+
+impl DataDescription{
+    fn to_bytes(&self) -> Vec<u8>{
+        match self {
+            DataDescription::Bytes(bytes) => bytes.clone(),
+            DataDescription::F32(values) => bytemuck::cast_slice(values).to_vec(),
+            DataDescription::U32(values) => bytemuck::cast_slice(values).to_vec(),
+            DataDescription::I32(values) => bytemuck::cast_slice(values).to_vec(),
+        }
+    }
+}
+
+// A PushUniform block from a description
+struct DataUniform(Vec<u8>);
+
+impl Uniform for DataUniform{
+    fn to_wgpu(&self) -> Vec<u8>{
+        self.0.clone()
+    }
+}
+
+// The node's uniform arena for PushUniform (RenderNode::add_uniform_arena)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UniformArenaDescription{
+    pub slot: BindSlot,
+    pub block_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UniformDescription{
+    pub name: String,
+    pub slot: BindSlot,
+}
+
+// A transform written out in the file. With a name, the transform registered under
+// that name is used instead (or this one is registered, if there isn't one yet), so
+// code can move the object after loading.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformDescription{
+    pub name: Option<String>,
+    pub position: [f32; 3],
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformDescription{
+    fn default() -> Self{
+        Self{
+            name: None,
+            position: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl TransformDescription{
+    fn to_transform(&self) -> Transform{
+        Transform::new(self.position.into(), self.rotation.into(), self.scale.into())
+    }
+}
+
+// Transforms a description wrote out by name, that weren't registered yet
+type NamedTransforms = Vec<(String, TransformHandle)>;
+
+// The Command variants that can be written as data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CommandDescription{
+    BindTexture{ path: String, slot: BindSlot },
+    BindTransient{ name: String, slot: BindSlot },
+    BindStorageBuffer{ name: String, slot: BindSlot },
+    DrawMesh{ path: String, #[serde(default)] transform: Option<TransformDescription> },
+    DrawMeshInstanced{ path: String, instances: Vec<TransformDescription> },
+    Draw{ vertices: (u32, u32), instances: (u32, u32) },
+    SetViewport{ x: f32, y: f32, width: f32, height: f32, min_depth: f32, max_depth: f32 },
+    SetScissor{ x: u32, y: u32, width: u32, height: u32 },
+    SetStencilReference(u32),
+    SetPushConstants{ stages: Vec<ShaderStageDescription>, offset: u32, data: DataDescription },
+    PushUniform(DataDescription),
+    DrawIndirect{ buffer: String, offset: u64 },
+    DrawIndexedIndirect{ mesh: String, buffer: String, offset: u64 },
+    MultiDrawIndexedIndirect{ mesh: String, buffer: String, offset: u64, count: u32 },
+}
+
+impl GraphDescription{
+    // Parse RON or JSON, picked by the file extension
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self>{
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read render graph {}", path.display()))?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => ron::from_str(&source).with_context(|| format!("Failed to parse {}", path.display())),
+            Some("json") => serde_json::from_str(&source).with_context(|| format!("Failed to parse {}", path.display())),
+            _ => bail!("Unknown render graph format {} (expected .ron or .json)", path.display()),
+        }
+    }
+
+    // Make the render nodes this describes, in order. They still need adding to the
+    // renderer. Also returns the named transforms the file wrote out that weren't
+    // registered yet, for the caller to register so code can move them.
+    pub(crate) fn create_nodes(&self, device: Handle<wgpu::Device>, queue: Handle<wgpu::Queue>,
+                               resource_manager: &ResourceManager) -> Result<(Vec<RenderNode>, NamedTransforms)>{
+        let mut new_transforms = Vec::new();
+
+        let nodes = self.nodes.iter()
+            .map(|description| description.create_node(device.clone(), queue.clone(), resource_manager, &mut new_transforms)
+                .with_context(|| format!("In render node \"{}\"", description.name)))
+            .collect::<Result<_>>()?;
+
+        Ok((nodes, new_transforms))
+    }
+}
+
+impl NodeDescription{
+    fn create_node(&self, device: Handle<wgpu::Device>, queue: Handle<wgpu::Queue>,
+                   resource_manager: &ResourceManager, new_transforms: &mut NamedTransforms) -> Result<RenderNode>{
+        let mut node = RenderNode::new(self.name.clone(), device, queue);
+        let settings = &self.settings;
+
+        if let Some(use_depth) = settings.use_depth {
+            node.use_depth(use_depth);
+        }
+        node.depth_only(settings.depth_only);
+        node.use_vertex_layout(settings.use_vertex_layout);
+        node.transparent(settings.transparent);
+        node.sort_front_to_back(settings.sort_front_to_back);
+        node.order_independent_transparency(settings.order_independent_transparency);
+
+        if let Some(count) = settings.sample_count {
+            node.set_sample_count(count);
+        }
+        if let Some([r, g, b, a]) = settings.clear_color {
+            node.set_clear_color(wgpu::Color{ r, g, b, a });
+        }
+        if let Some(depth) = settings.clear_depth {
+            node.set_clear_depth(depth);
+        }
+        if let Some(stencil) = settings.clear_stencil {
+            node.set_clear_stencil(stencil);
+        }
+        if let Some(slot) = settings.draw_transform_slot {
+            node.set_draw_transform_slot(slot);
+        }
+        if let Some(stencil) = &settings.stencil_state {
+            node.set_stencil_state(stencil.0.clone());
+        }
+        for range in settings.push_constant_ranges.iter() {
+            node.add_push_constant_range(range.to_stages(), range.range.0..range.range.1);
+        }
+        if let Some(slot) = settings.push_constant_fallback_slot {
+            node.set_push_constant_fallback_slot(slot);
+        }
+        if let Some(arena) = &settings.uniform_arena {
+            node.add_uniform_arena(arena.slot, arena.block_size);
+        }
+        if let Some(name) = &settings.transient_target {
+            node.render_to_transient(name);
+        }
+        node.set_run_policy(settings.run_policy.clone());
+
+        for uniform in self.uniforms.iter() {
+            let buffer = resource_manager.get_named_uniform_buffer(&uniform.name)
+                .ok_or_else(|| anyhow!("No uniform buffer registered as {}", uniform.name))?;
+            node.add_raw_uniform_buffer(buffer, uniform.slot);
+        }
+
+        node.add_command(Command::LoadShader(self.shader.clone()));
+        for define in self.defines.iter() {
+            node.add_command(Command::Define(define.clone()));
+        }
+
+        for command in self.commands.iter() {
+            node.add_command(command.to_command(resource_manager, new_transforms));
+        }
+
+        Ok(node)
+    }
+}
+
+impl CommandDescription{
+    fn to_command(&self, resource_manager: &ResourceManager, new_transforms: &mut NamedTransforms) -> Command{
+        match self {
+            CommandDescription::BindTexture{ path, slot } => Command::BindTexture(*slot, path.clone()),
+            CommandDescription::BindTransient{ name, slot } => Command::BindTransient(*slot, name.clone()),
+            CommandDescription::BindStorageBuffer{ name, slot } => Command::BindStorageBuffer(*slot, name.clone()),
+            CommandDescription::DrawMesh{ path, transform } => {
+                // A name used again later in the file shares the transform made the first time
+                let transform = transform.as_ref().map(|description| match &description.name {
+                    Some(name) => resource_manager.get_named_transform(name)
+                        .or_else(|| new_transforms.iter().find(|(new_name, _)| new_name == name).map(|(_, transform)| transform.clone()))
+                        .unwrap_or_else(|| {
+                            let transform = TransformHandle::new(description.to_transform());
+                            new_transforms.push((name.clone(), transform.clone()));
+                            transform
+                        }),
+                    None => TransformHandle::new(description.to_transform()),
+                });
+
+                Command::DrawMesh(path.clone(), transform)
+            }
+            CommandDescription::DrawMeshInstanced{ path, instances } => {
+                Command::DrawMeshInstanced(path.clone(), instances.iter().map(|instance| instance.to_transform()).collect())
+            }
+            CommandDescription::Draw{ vertices, instances } => Command::Draw{
+                vertices: vertices.0..vertices.1,
+                instances: instances.0..instances.1,
+            },
+            CommandDescription::SetViewport{ x, y, width, height, min_depth, max_depth } => Command::SetViewport{
+                x: *x, y: *y, width: *width, height: *height, min_depth: *min_depth, max_depth: *max_depth,
+            },
+            CommandDescription::SetScissor{ x, y, width, height } => Command::SetScissor{
+                x: *x, y: *y, width: *width, height: *height,
+            },
+            CommandDescription::SetStencilReference(reference) => Command::SetStencilReference(*reference),
+            CommandDescription::SetPushConstants{ stages, offset, data } => {
+                Command::SetPushConstants(shader_stages(stages), *offset, data.to_bytes())
+            }
+            CommandDescription::PushUniform(data) => Command::PushUniform(Box::new(DataUniform(data.to_bytes()))),
+            CommandDescription::DrawIndirect{ buffer, offset } => Command::DrawIndirect{ buffer: buffer.clone(), offset: *offset },
+            CommandDescription::DrawIndexedIndirect{ mesh, buffer, offset } => Command::DrawIndexedIndirect{
+                mesh: mesh.clone(), buffer: buffer.clone(), offset: *offset,
+            },
+            CommandDescription::MultiDrawIndexedIndirect{ mesh, buffer, offset, count } => Command::MultiDrawIndexedIndirect{
+                mesh: mesh.clone(), buffer: buffer.clone(), offset: *offset, count: *count,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_example_graph() {
+        let graph = GraphDescription::load("examples/graphs/cube.ron").unwrap();
+
+        assert_eq!(graph.nodes.len(), 1);
+        let node = &graph.nodes[0];
+        assert_eq!(node.name, "Normal Cube");
+        assert_eq!(node.shader, "examples/shaders/cube.wgsl");
+        assert_eq!(node.settings.use_depth, Some(true));
+        // Settings it doesn't mention keep their defaults
+        assert!(node.settings.use_vertex_layout);
+        assert_eq!(node.settings.run_policy, RunPolicy::Always);
+
+        assert_eq!(node.uniforms.len(), 1);
+        assert_eq!((node.uniforms[0].name.as_str(), node.uniforms[0].slot), ("camera", BindSlot::new(0, 0)));
+
+        assert_eq!(node.commands.len(), 3);
+        match &node.commands[1] {
+            CommandDescription::DrawMesh{ transform: Some(transform), .. } => {
+                assert_eq!(transform.name.as_deref(), Some("spinning_cube"));
+                assert_eq!(transform.position, [0.0, 0.0, -20.0]);
+                assert_eq!(transform.scale, [1.0; 3]);
+            }
+            command => panic!("expected a named DrawMesh, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn parses_stencil_push_constant_and_arena_settings() {
+        let settings: NodeSettings = ron::from_str(r#"(
+            stencil_state: Some((
+                front: (compare: Equal, pass_op: Replace),
+                write_mask: 0x0f,
+            )),
+            push_constant_ranges: [(stages: [Vertex, Fragment], range: (0, 64))],
+            push_constant_fallback_slot: Some((group: 2, binding: 0)),
+            uniform_arena: Some((slot: (group: 3, binding: 0), block_size: 256)),
+        )"#).unwrap();
+
+        let stencil = settings.stencil_state.unwrap().0;
+        assert_eq!(stencil.front.compare, wgpu::CompareFunction::Equal);
+        assert_eq!(stencil.front.pass_op, wgpu::StencilOperation::Replace);
+        assert_eq!(stencil.front.fail_op, wgpu::StencilOperation::Keep);
+        assert_eq!(stencil.back, wgpu::StencilFaceState::IGNORE);
+        assert_eq!((stencil.read_mask, stencil.write_mask), (0xff, 0x0f));
+
+        assert_eq!(settings.push_constant_ranges[0].to_stages(), wgpu::ShaderStages::VERTEX_FRAGMENT);
+        assert_eq!(settings.push_constant_ranges[0].range, (0, 64));
+        assert_eq!(settings.push_constant_fallback_slot, Some(BindSlot::new(2, 0)));
+        let arena = settings.uniform_arena.unwrap();
+        assert_eq!((arena.slot, arena.block_size), (BindSlot::new(3, 0), 256));
+    }
+
+    #[test]
+    fn parses_json_commands() {
+        let graph: GraphDescription = serde_json::from_str(r#"{
+            "nodes": [{
+                "name": "Fullscreen",
+                "shader": "post.wgsl",
+                "settings": { "use_vertex_layout": false, "run_policy": { "EveryNFrames": 2 } },
+                "commands": [
+                    { "SetViewport": { "x": 0, "y": 0, "width": 64, "height": 64, "min_depth": 0, "max_depth": 1 } },
+                    { "Draw": { "vertices": [0, 3], "instances": [0, 1] } }
+                ]
+            }]
+        }"#).unwrap();
+
+        let node = &graph.nodes[0];
+        assert!(!node.settings.use_vertex_layout);
+        assert_eq!(node.settings.use_depth, None);
+        assert_eq!(node.settings.run_policy, RunPolicy::EveryNFrames(2));
+        assert!(node.defines.is_empty() && node.uniforms.is_empty());
+        assert!(matches!(node.commands[1], CommandDescription::Draw{ vertices: (0, 3), instances: (0, 1) }));
+    }
+
+    #[test]
+    fn use_depth_is_only_set_when_written() {
+        let settings = NodeSettings{ use_depth: Some(false), transparent: true, ..Default::default() };
+        let written = ron::to_string(&settings).unwrap();
+        assert!(written.contains("use_depth:false"));
+        assert_eq!(ron::from_str::<NodeSettings>(&written).unwrap().use_depth, Some(false));
+
+        let written = ron::to_string(&NodeSettings::default()).unwrap();
+        assert!(!written.contains("use_depth"));
+        assert_eq!(ron::from_str::<NodeSettings>(&written).unwrap().use_depth, None);
+    }
+
+    #[test]
+    fn parses_push_constant_and_uniform_data() {
+        let commands: Vec<CommandDescription> = ron::from_str(r#"[
+            SetPushConstants(stages: [Fragment], offset: 16, data: F32([1.0, 0.5])),
+            PushUniform(U32([7, 0, 0, 0])),
+            PushUniform(Bytes([1, 2, 3, 4])),
+        ]"#).unwrap();
+
+        match &commands[0] {
+            CommandDescription::SetPushConstants{ stages, offset, data } => {
+                assert_eq!((shader_stages(stages), *offset), (wgpu::ShaderStages::FRAGMENT, 16));
+                assert_eq!(data.to_bytes(), [1.0f32.to_ne_bytes(), 0.5f32.to_ne_bytes()].concat());
+            }
+            command => panic!("expected SetPushConstants, got {:?}", command),
+        }
+
+        assert!(matches!(&commands[1], CommandDescription::PushUniform(data) if data.to_bytes().len() == 16));
+        assert!(matches!(&commands[2], CommandDescription::PushUniform(data) if data.to_bytes() == [1, 2, 3, 4]));
+        assert_eq!(DataUniform(vec![1, 2, 3, 4]).to_wgpu(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_files() {
+        assert!(GraphDescription::load("examples/graphs/missing.ron").is_err());
+
+        let dir = std::env::temp_dir().join(format!("minirender_description_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("graph.toml"), "nodes = []").unwrap();
+
+        let error = GraphDescription::load(dir.join("graph.toml")).unwrap_err();
+        assert!(error.to_string().starts_with("Unknown render graph format"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
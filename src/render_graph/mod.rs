@@ -1,36 +1,523 @@
 mod render_node;
 mod commands;
 mod resource_manager;
+mod build_error;
+mod oit;
+mod description;
+mod texture_pool;
+mod subgraph;
+mod run_policy;
 
 pub use render_node::RenderNode;
+use render_node::NodeResource;
 pub use commands::{Command, DrawCommand};
 pub use resource_manager::{ResourceManager, ResourceHandle, ResourceType};
+pub use build_error::{BuildError, BuildReport};
+pub(crate) use oit::OitTargets;
+pub use run_policy::RunPolicy;
+pub use subgraph::{RenderGraphTemplate, SubgraphBuilder, SubgraphParameters};
+use subgraph::Subgraph;
+pub use texture_pool::{TransientMemoryReport, TransientSize, TransientTextureDescriptor};
+pub use description::{CommandDescription, DataDescription, GraphDescription, NodeDescription, NodeSettings, PushConstantRangeDescription, ShaderStageDescription,
+                      StencilDescription, TransformDescription, UniformArenaDescription, UniformDescription};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::error;
+
 use crate::MutHandle;
 
+// Identifies a node in the graph for as long as it's in it. IDs aren't reused, and
+// are unique across every graph and subgraph, so one graph's ID never names a node in another.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId(u64);
+
+impl NodeId{
+    fn next() -> Self{
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        NodeId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+// A node in the graph runs a single render node, or a whole subgraph in its place
+enum GraphEntry{
+    Node(Box<RenderNode>),
+    Subgraph(Subgraph),
+}
+
+struct GraphNode{
+    id: NodeId,
+    entry: GraphEntry,
+    // Disabled nodes stay built but are skipped each frame
+    enabled: bool,
+}
+
 pub struct RenderGraph{
-    nodes: Vec<RenderNode>,
+    nodes: Vec<GraphNode>,
+
+    // Set once the graph has been built - nodes added after that are built straight away
+    built: bool,
 }
 
 impl RenderGraph{
     pub fn new() -> Self{
         Self{
             nodes: Vec::new(),
+
+            built: false,
+        }
+    }
+
+    fn position(&self, id: NodeId) -> Option<usize>{
+        self.nodes.iter().position(|entry| entry.id == id)
+    }
+
+    fn try_build_entry(entry: &mut GraphEntry, resource_manager: MutHandle<ResourceManager>) -> Result<(), BuildReport>{
+        match entry {
+            GraphEntry::Node(node) => node.build_pipeline(resource_manager).map_err(|errors| {
+                let mut report = BuildReport::default();
+                report.add(&node.name, errors);
+                report
+            }),
+            GraphEntry::Subgraph(subgraph) => subgraph.graph.build(resource_manager),
+        }
+    }
+
+    // Add an entry without building it, for graphs that get built later
+    fn insert_entry(&mut self, entry: GraphEntry) -> NodeId{
+        let id = NodeId::next();
+
+        self.nodes.push(GraphNode{ id, entry, enabled: true });
+        id
+    }
+
+    // Once the graph is built, entries are only added if they build
+    fn push_entry(&mut self, mut entry: GraphEntry, resource_manager: MutHandle<ResourceManager>) -> Result<NodeId, BuildReport>{
+        if self.built{
+            Self::try_build_entry(&mut entry, resource_manager)?;
         }
+
+        Ok(self.insert_entry(entry))
     }
 
-    pub fn add_node(&mut self, node: RenderNode){
-        self.nodes.push(node);
+    pub fn add_node(&mut self, node: RenderNode, resource_manager: MutHandle<ResourceManager>) -> Result<NodeId, BuildReport>{
+        self.push_entry(GraphEntry::Node(Box::new(node)), resource_manager)
     }
 
-    pub fn build(&mut self, resource_manager: MutHandle<ResourceManager>){
+    // Embed a subgraph, which runs all its nodes where it sits in this graph
+    pub(crate) fn add_subgraph(&mut self, subgraph: Subgraph, resource_manager: MutHandle<ResourceManager>) -> Result<NodeId, BuildReport>{
+        self.push_entry(GraphEntry::Subgraph(subgraph), resource_manager)
+    }
+
+    // Removing a subgraph drops it, so only render nodes are handed back
+    pub fn remove_node(&mut self, id: NodeId) -> Option<RenderNode>{
+        let index = self.position(id)?;
+        match self.nodes.remove(index).entry {
+            GraphEntry::Node(node) => Some(*node),
+            GraphEntry::Subgraph(_) => None,
+        }
+    }
+
+    // Swap in a new node at the same place in the graph, keeping its ID and enabled
+    // state. Returns the old node, if it wasn't a subgraph. If the new node doesn't
+    // build, the old one stays.
+    pub fn replace_node(&mut self, id: NodeId, node: RenderNode, resource_manager: MutHandle<ResourceManager>) -> Result<Option<RenderNode>, BuildReport>{
+        let index = self.position(id).ok_or_else(|| BuildReport::unknown_node(id))?;
+
+        let mut entry = GraphEntry::Node(Box::new(node));
+        if self.built{
+            Self::try_build_entry(&mut entry, resource_manager)?;
+        }
+
+        Ok(match std::mem::replace(&mut self.nodes[index].entry, entry) {
+            GraphEntry::Node(node) => Some(*node),
+            GraphEntry::Subgraph(_) => None,
+        })
+    }
+
+    // Give every node in a subgraph new parameters, rebuilding them if the graph is built
+    pub fn set_subgraph_parameters(&mut self, id: NodeId, parameters: SubgraphParameters, resource_manager: MutHandle<ResourceManager>) -> Result<(), BuildReport>{
+        let built = self.built;
+        let index = self.position(id).ok_or_else(|| BuildReport::unknown_node(id))?;
+        let GraphEntry::Subgraph(subgraph) = &mut self.nodes[index].entry else {
+            return Err(BuildReport::node_error(id, BuildError::NotASubgraph(id)));
+        };
+
+        subgraph.set_parameters(parameters);
+        if built{
+            subgraph.graph.build(resource_manager)?;
+        }
+        Ok(())
+    }
+
+    // Move a node so it runs at `index` in the graph (clamped to the end)
+    pub fn move_node(&mut self, id: NodeId, index: usize) -> bool{
+        let Some(current) = self.position(id) else {
+            return false;
+        };
+
+        let entry = self.nodes.remove(current);
+        self.nodes.insert(index.min(self.nodes.len()), entry);
+        true
+    }
+
+    pub fn set_node_enabled(&mut self, id: NodeId, enabled: bool) -> bool{
+        match self.position(id) {
+            Some(index) => {
+                self.nodes[index].enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Have an OnDemand node (or every node in a subgraph) run next frame
+    pub fn request_run(&mut self, id: NodeId) -> bool{
+        let Some(index) = self.position(id) else {
+            return false;
+        };
+
+        match &mut self.nodes[index].entry {
+            GraphEntry::Node(node) => node.request_run(),
+            GraphEntry::Subgraph(subgraph) => subgraph.graph.enabled_nodes_mut().into_iter().for_each(|node| node.request_run()),
+        }
+        true
+    }
+
+    pub fn is_node_enabled(&self, id: NodeId) -> Option<bool>{
+        self.position(id).map(|index| self.nodes[index].enabled)
+    }
+
+    // Change a node's commands or settings, then call `rebuild_node`
+    pub fn get_node_mut(&mut self, id: NodeId) -> Option<&mut RenderNode>{
+        let index = self.position(id)?;
+        match &mut self.nodes[index].entry {
+            GraphEntry::Node(node) => Some(node),
+            GraphEntry::Subgraph(_) => None,
+        }
+    }
+
+    // Build a single node (or every node in a subgraph) again, leaving the rest alone.
+    // Shader files edited since the last build are compiled again.
+    pub fn rebuild_node(&mut self, id: NodeId, resource_manager: MutHandle<ResourceManager>) -> Result<(), BuildReport>{
+        let index = self.position(id).ok_or_else(|| BuildReport::unknown_node(id))?;
+
+        Self::try_build_entry(&mut self.nodes[index].entry, resource_manager)
+    }
+
+    // Build every node, collecting all failures into one report
+    pub fn build(&mut self, resource_manager: MutHandle<ResourceManager>) -> Result<(), BuildReport>{
+        let mut report = BuildReport::default();
+        self.built = true;
+
         for node in self.nodes.iter_mut(){
-            node.build_pipeline(resource_manager.clone());
+            if let Err(failures) = Self::try_build_entry(&mut node.entry, resource_manager.clone()){
+                report.failures.extend(failures.failures);
+            }
+        }
+
+        for (node, frame) in mixed_sample_counts(&self.enabled_nodes()){
+            report.add(&node.name, vec![BuildError::MixedSampleCounts{ count: node.get_sample_count(), frame }]);
         }
+
+        if report.is_empty() {
+            Ok(())
+        } else {
+            Err(report)
+        }
+    }
+
+    // The render nodes directly in this graph, not in its subgraphs
+    pub(crate) fn direct_nodes_mut(&mut self) -> impl Iterator<Item = &mut RenderNode>{
+        self.nodes.iter_mut().filter_map(|entry| match &mut entry.entry {
+            GraphEntry::Node(node) => Some(node.as_mut()),
+            GraphEntry::Subgraph(_) => None,
+        })
     }
 
-    pub fn execute(&self, texture_view: &wgpu::TextureView, resource_manager: MutHandle<ResourceManager>, encoder: &mut wgpu::CommandEncoder){
-        for (id, node) in self.nodes.iter().enumerate(){
-            node.execute(id, texture_view, resource_manager.clone(), encoder);
+    // Every enabled render node in execution order, with subgraphs expanded in place
+    fn enabled_nodes(&self) -> Vec<&RenderNode>{
+        let mut nodes = Vec::new();
+        for entry in self.nodes.iter().filter(|entry| entry.enabled){
+            match &entry.entry {
+                GraphEntry::Node(node) => nodes.push(node.as_ref()),
+                GraphEntry::Subgraph(subgraph) => nodes.extend(subgraph.graph.enabled_nodes()),
+            }
         }
+        nodes
     }
-}
\ No newline at end of file
+
+    fn enabled_nodes_mut(&mut self) -> Vec<&mut RenderNode>{
+        let mut nodes = Vec::new();
+        for entry in self.nodes.iter_mut().filter(|entry| entry.enabled){
+            match &mut entry.entry {
+                GraphEntry::Node(node) => nodes.push(node.as_mut()),
+                GraphEntry::Subgraph(subgraph) => nodes.extend(subgraph.graph.enabled_nodes_mut()),
+            }
+        }
+        nodes
+    }
+
+    // The graph in Graphviz DOT: nodes in execution order with their shaders, and an
+    // edge from every texture, mesh and buffer to each node that uses it, so shared
+    // resources show up as one box with several edges. Subgraphs are drawn as
+    // clusters. Render it with `dot -Tsvg`.
+    pub fn to_dot(&self) -> String{
+        let mut writer = DotWriter::default();
+        self.write_dot(&mut writer, "", true, 1);
+
+        let mut dot = String::from("digraph RenderGraph {\n    rankdir=LR;\n    node [fontname=\"Helvetica\"];\n\n");
+        dot += &writer.resources;
+        dot += "\n";
+        dot += &writer.nodes;
+        dot += "\n";
+        dot += &writer.edges;
+
+        // Execution order
+        for pair in writer.order.windows(2){
+            let _ = writeln!(dot, "    {} -> {} [style=bold, color=gray40];", pair[0], pair[1]);
+        }
+
+        dot += "}\n";
+        dot
+    }
+
+    fn write_dot(&self, writer: &mut DotWriter, prefix: &str, enabled: bool, depth: usize){
+        let indent = "    ".repeat(depth);
+
+        for entry in self.nodes.iter(){
+            let node_id = format!("{}node{}", prefix, entry.id.0);
+            let enabled = enabled && entry.enabled;
+
+            let node = match &entry.entry {
+                GraphEntry::Node(node) => node,
+                GraphEntry::Subgraph(subgraph) => {
+                    let style = if enabled { "rounded" } else { "rounded,dashed" };
+                    let _ = writeln!(writer.nodes, "{}subgraph cluster_{} {{", indent, node_id);
+                    let _ = writeln!(writer.nodes, "{}    label=\"{}\";\n{}    style=\"{}\";", indent, dot_escape(&subgraph.name), indent, style);
+                    subgraph.graph.write_dot(writer, &format!("{}_", node_id), enabled, depth + 1);
+                    let _ = writeln!(writer.nodes, "{}}}", indent);
+                    continue;
+                }
+            };
+
+            let mut label = format!("{}. {}", writer.order.len(), node.name);
+            match node.shader_permutation() {
+                Some(permutation) => {
+                    label += &format!("\n{}", permutation.path());
+                    if !permutation.defines().is_empty() {
+                        label += &format!("\n[{}]", permutation.defines().iter().cloned().collect::<Vec<_>>().join(", "));
+                    }
+                }
+                None => label += "\n(no shader)",
+            }
+            if *node.get_run_policy() != RunPolicy::Always{
+                label += &format!("\nruns: {:?}", node.get_run_policy());
+            }
+
+            let style = if enabled { "solid" } else { "dashed" };
+            let _ = writeln!(writer.nodes, "{}{} [shape=box, style={}, label=\"{}\"];", indent, node_id, style, dot_escape(&label));
+            writer.order.push(node_id.clone());
+
+            for resource in node.get_resources(){
+                let (key, shape, resource_label, edge_label) = match resource {
+                    NodeResource::Texture(path, slot) => (format!("texture:{}", path), "ellipse", path, dot_slot(slot)),
+                    NodeResource::Mesh(path) => (format!("mesh:{}", path), "component", path, "draw".to_string()),
+                    NodeResource::StorageBuffer(name, slot) => {
+                        let edge_label = slot.map(dot_slot).unwrap_or_else(|| "indirect".to_string());
+                        (format!("storage:{}", name), "cylinder", name, edge_label)
+                    }
+                    NodeResource::Transient(name, Some(slot)) => (format!("transient:{}", name), "box3d", name, dot_slot(slot)),
+                    NodeResource::Transient(name, None) => {
+                        // Drawn the other way, from the node into the texture
+                        let resource_id = writer.declare_resource(format!("transient:{}", name), "box3d", &name);
+                        let _ = writeln!(writer.edges, "    {} -> {} [label=\"renders to\"];", node_id, resource_id);
+                        continue;
+                    }
+                    NodeResource::UniformBuffer(address, slot) => {
+                        let next = writer.uniform_ids.len();
+                        let number = *writer.uniform_ids.entry(address).or_insert(next);
+                        (format!("uniform:{}", number), "note", format!("uniform buffer {}", number), dot_slot(slot))
+                    }
+                };
+
+                let resource_id = writer.declare_resource(key, shape, &resource_label);
+                let _ = writeln!(writer.edges, "    {} -> {} [label=\"{}\"];", resource_id, node_id, dot_escape(&edge_label));
+            }
+        }
+    }
+
+    pub fn execute(&mut self, texture_view: &wgpu::TextureView, resource_manager: MutHandle<ResourceManager>, encoder: &mut wgpu::CommandEncoder){
+        // Transient textures live from the first node that uses them to the last, so
+        // ones that don't overlap can share memory
+        let mut lifetimes = HashMap::new();
+        let mut depth_lifetimes = HashMap::new();
+        // Targets of nodes that can skip frames have to hold on to what they drew
+        let mut persistent = HashSet::new();
+
+        for (index, node) in self.enabled_nodes().into_iter().enumerate(){
+            if let Some(name) = node.get_transient_target(){
+                extend_lifetime(&mut lifetimes, name, index);
+                if *node.get_run_policy() != RunPolicy::Always{
+                    persistent.insert(name.to_string());
+                }
+                if node.uses_depth_attachment(){
+                    extend_lifetime(&mut depth_lifetimes, name, index);
+                }
+            }
+
+            for name in node.get_transient_reads(){
+                extend_lifetime(&mut lifetimes, name, index);
+            }
+        }
+
+        resource_manager.lock().unwrap().allocate_transients(lifetimes, depth_lifetimes, &persistent);
+
+        // Rebuild any bind groups (and pipelines) that went stale since the last frame,
+        // for the nodes whose run policy has them running
+        for node in self.enabled_nodes_mut(){
+            if !node.update_run_state(&resource_manager.lock().unwrap()){
+                continue;
+            }
+
+            if let Err(errors) = node.prepare(resource_manager.clone()){
+                let mut report = BuildReport::default();
+                report.add(&node.name, errors);
+                error!("{}", report);
+            }
+        }
+
+        // Nodes at another sample count than the frame's would clear or resolve over
+        // what the others drew, so they're skipped (the build reported them)
+        let enabled = self.enabled_nodes();
+        let mixed = mixed_sample_counts(&enabled);
+        let nodes: Vec<&RenderNode> = enabled.into_iter()
+            .filter(|node| node.runs_this_frame() && !mixed.iter().any(|(other, _)| std::ptr::eq(*other, *node)))
+            .collect();
+
+        // The frame (with its MSAA target) is cleared by the first node drawing to it, the
+        // depth texture by the first to use it. OIT nodes only use the depth texture, so
+        // when they come first the frame is cleared by their composite.
+        let mut frame_cleared = false;
+        let mut depth_cleared = false;
+        // Transient textures are cleared by the first node writing them, as they may hold another's contents
+        let mut written_transients = HashSet::new();
+
+        for (index, node) in nodes.iter().enumerate(){
+            let sample_count = node.get_sample_count();
+            let (clear_color, clear_depth) = match node.get_transient_target() {
+                Some(name) => {
+                    let first_use = written_transients.insert(name);
+                    (first_use, first_use)
+                }
+                None => {
+                    let clear_color = !node.is_oit() && !std::mem::replace(&mut frame_cleared, true);
+                    (clear_color, !std::mem::replace(&mut depth_cleared, true))
+                }
+            };
+
+            // OIT nodes in a row share the OIT targets: the first clears them, and
+            // they're composited over the frame after the last
+            let continues_oit = |other: Option<&RenderNode>| {
+                other.is_some_and(|other| other.is_oit() && other.get_sample_count() == sample_count)
+            };
+            let clear_oit = !continues_oit(index.checked_sub(1).and_then(|previous| nodes.get(previous).copied()));
+
+            node.execute(clear_color, clear_depth, clear_oit, texture_view, resource_manager.clone(), encoder);
+
+            if node.is_oit() && !continues_oit(nodes.get(index + 1).copied()){
+                let clear_frame = !std::mem::replace(&mut frame_cleared, true);
+                resource_manager.lock().unwrap().composite_oit(sample_count, clear_frame, texture_view, encoder);
+            }
+        }
+    }
+}
+
+// Every node drawing to the frame shares one MSAA target, so they have to agree on a
+// sample count: the first built one sets it. Returns the nodes that differ, with the frame's count.
+fn mixed_sample_counts<'a>(nodes: &[&'a RenderNode]) -> Vec<(&'a RenderNode, u32)>{
+    let mut frame_nodes = nodes.iter().filter(|node| node.is_built() && node.get_transient_target().is_none());
+    let Some(frame) = frame_nodes.next().map(|node| node.get_sample_count()) else {
+        return Vec::new();
+    };
+
+    frame_nodes.filter(|node| node.get_sample_count() != frame).map(|node| (*node, frame)).collect()
+}
+
+fn extend_lifetime(lifetimes: &mut HashMap<String, (usize, usize)>, name: &str, index: usize){
+    lifetimes.entry(name.to_string()).or_insert((index, index)).1 = index;
+}
+
+// What RenderGraph::to_dot has written so far, across subgraphs
+#[derive(Default)]
+struct DotWriter{
+    resources: String,
+    nodes: String,
+    edges: String,
+    // Resource key -> DOT id, so each resource is declared once
+    resource_ids: HashMap<String, String>,
+    // Uniform buffers are numbered in the order they're first seen
+    uniform_ids: HashMap<usize, usize>,
+    // Render node DOT ids in execution order
+    order: Vec<String>,
+}
+
+impl DotWriter{
+    // The DOT id for a resource, declaring it the first time it's seen
+    fn declare_resource(&mut self, key: String, shape: &str, label: &str) -> String{
+        if let Some(id) = self.resource_ids.get(&key){
+            return id.clone();
+        }
+
+        let id = format!("resource{}", self.resource_ids.len());
+        let _ = writeln!(self.resources, "    {} [shape={}, label=\"{}\"];", id, shape, dot_escape(label));
+        self.resource_ids.insert(key, id.clone());
+        id
+    }
+}
+
+fn dot_slot(slot: crate::types::BindSlot) -> String{
+    format!("@group({}) @binding({})", slot.group, slot.binding)
+}
+
+fn dot_escape(text: &str) -> String{
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn escapes_quotes_backslashes_and_newlines(){
+        assert_eq!(dot_escape(r#"say "hi""#), r#"say \"hi\""#);
+        assert_eq!(dot_escape(r"C:\shaders\cube.wgsl"), r"C:\\shaders\\cube.wgsl");
+        assert_eq!(dot_escape("0. Cube\nshader.wgsl"), r"0. Cube\nshader.wgsl");
+        // A backslash before a quote stays a literal backslash, not an escape
+        assert_eq!(dot_escape(r#"\""#), r#"\\\""#);
+    }
+
+    #[test]
+    fn resources_are_declared_once_with_escaped_labels(){
+        let mut writer = DotWriter::default();
+
+        let first = writer.declare_resource("texture:a".to_string(), "ellipse", "textures/\"a\".png");
+        let again = writer.declare_resource("texture:a".to_string(), "ellipse", "textures/\"a\".png");
+        let other = writer.declare_resource("texture:b".to_string(), "ellipse", "b.png");
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert_eq!(writer.resources.lines().count(), 2);
+        assert!(writer.resources.contains(r#"label="textures/\"a\".png""#));
+    }
+
+    #[test]
+    fn empty_graph_is_valid_dot(){
+        let dot = RenderGraph::new().to_dot();
+
+        assert!(dot.starts_with("digraph RenderGraph {"));
+        assert!(dot.ends_with("}\n"));
+        assert!(!dot.contains("->"));
+    }
+}
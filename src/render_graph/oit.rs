@@ -0,0 +1,283 @@
+use crate::MutHandle;
+use crate::shader::ShaderPreprocessor;
+
+// Weighted blended order-independent transparency. OIT nodes draw into an
+// accumulation and a revealage target instead of the frame, with blending that
+// doesn't depend on draw order, and the result is composited over the frame
+// after the last OIT node in a run.
+pub struct OitTargets{
+    // Drawn into at the node's sample count
+    pub accum: wgpu::TextureView,
+    pub reveal: wgpu::TextureView,
+    // Single-sample copies the MSAA targets resolve into, for the composite to read
+    pub resolved: Option<(wgpu::TextureView, wgpu::TextureView)>,
+
+    bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+}
+
+impl OitTargets{
+    pub const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const REVEAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    pub fn new(device: &wgpu::Device, sc_desc: MutHandle<wgpu::SurfaceConfiguration>, sample_count: u32,
+               compositor: &OitCompositor) -> Self{
+        let sc_desc = sc_desc.lock().unwrap();
+        let size = (sc_desc.width, sc_desc.height);
+
+        let accum = Self::create_view(device, size, Self::ACCUM_FORMAT, sample_count);
+        let reveal = Self::create_view(device, size, Self::REVEAL_FORMAT, sample_count);
+
+        let resolved = (sample_count > 1).then(|| (
+            Self::create_view(device, size, Self::ACCUM_FORMAT, 1),
+            Self::create_view(device, size, Self::REVEAL_FORMAT, 1),
+        ));
+
+        let (accum_source, reveal_source) = match &resolved {
+            Some((accum, reveal)) => (accum, reveal),
+            None => (&accum, &reveal),
+        };
+        let bind_group = compositor.create_bind_group(device, accum_source, reveal_source);
+
+        Self{
+            accum,
+            reveal,
+            resolved,
+
+            bind_group,
+            size,
+        }
+    }
+
+    fn create_view(device: &wgpu::Device, size: (u32, u32), format: wgpu::TextureFormat, sample_count: u32) -> wgpu::TextureView{
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // Multisampled targets are only drawn into, the composite reads the resolved ones
+            usage: if sample_count > 1 {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            },
+            label: Some("OIT Target"),
+            view_formats: &[],
+        });
+
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn get_size(&self) -> (u32, u32){
+        self.size
+    }
+
+    // The accumulation and revealage attachments, cleared to no coverage when `clear` is set
+    pub fn color_attachments(&self, clear: bool) -> [Option<wgpu::RenderPassColorAttachment<'_>>; 2]{
+        let (accum_resolve, reveal_resolve) = match &self.resolved {
+            Some((accum, reveal)) => (Some(accum), Some(reveal)),
+            None => (None, None),
+        };
+
+        let ops = |clear_color: wgpu::Color| wgpu::Operations {
+            load: if clear { wgpu::LoadOp::Clear(clear_color) } else { wgpu::LoadOp::Load },
+            store: wgpu::StoreOp::Store,
+        };
+
+        [
+            Some(wgpu::RenderPassColorAttachment {
+                view: &self.accum,
+                resolve_target: accum_resolve,
+                ops: ops(wgpu::Color::TRANSPARENT),
+            }),
+            Some(wgpu::RenderPassColorAttachment {
+                view: &self.reveal,
+                resolve_target: reveal_resolve,
+                ops: ops(wgpu::Color::WHITE),
+            }),
+        ]
+    }
+
+    // The color targets an OIT node's pipeline renders to
+    pub fn color_target_states() -> [Option<wgpu::ColorTargetState>; 2]{
+        [
+            // Sum of weighted, premultiplied colors
+            Some(wgpu::ColorTargetState {
+                format: Self::ACCUM_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            // Product of (1 - alpha), how much of the background still shows
+            Some(wgpu::ColorTargetState {
+                format: Self::REVEAL_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+        ]
+    }
+}
+
+// Where the composite draws: the frame's color target (multisampled, resolving into
+// the frame, with MSAA), cleared first when no node has drawn to it yet
+pub struct CompositeTarget<'a>{
+    pub view: &'a wgpu::TextureView,
+    pub resolve_target: Option<&'a wgpu::TextureView>,
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    pub clear: bool,
+}
+
+// The fullscreen pass that blends the OIT targets over the frame. Pipelines are
+// built per sample count, to match the frame's (multisampled) color target.
+pub struct OitCompositor{
+    shader: wgpu::ShaderModule,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: Vec<(u32, wgpu::TextureFormat, wgpu::RenderPipeline)>,
+}
+
+impl OitCompositor{
+    pub fn new(device: &wgpu::Device) -> Self{
+        let source = ShaderPreprocessor::new()
+            .process_str(include_str!("oit_composite.wgsl"), ".")
+            .expect("Failed to preprocess the OIT composite shader");
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("OIT Composite"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("OIT Composite Bind Group Layout"),
+            entries: &[texture_entry(0), texture_entry(1)],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("OIT Composite Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self{
+            shader,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines: Vec::new(),
+        }
+    }
+
+    fn create_bind_group(&self, device: &wgpu::Device, accum: &wgpu::TextureView, reveal: &wgpu::TextureView) -> wgpu::BindGroup{
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("OIT Composite Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(accum),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(reveal),
+                },
+            ],
+        })
+    }
+
+    fn load_pipeline(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, sample_count: u32) -> &wgpu::RenderPipeline{
+        let index = match self.pipelines.iter().position(|(count, f, _)| *count == sample_count && *f == format) {
+            Some(index) => index,
+            None => {
+                let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("OIT Composite Pipeline"),
+                    layout: Some(&self.pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &self.shader,
+                        entry_point: "vert_main",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &self.shader,
+                        entry_point: "frag_main",
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState {
+                        count: sample_count,
+                        ..Default::default()
+                    },
+                    multiview: None,
+                });
+
+                self.pipelines.push((sample_count, format, pipeline));
+                self.pipelines.len() - 1
+            }
+        };
+
+        &self.pipelines[index].2
+    }
+
+    // Blend `targets` over `target`
+    pub fn composite(&mut self, device: &wgpu::Device, targets: &OitTargets, target: CompositeTarget, encoder: &mut wgpu::CommandEncoder){
+        let pipeline = self.load_pipeline(device, target.format, target.sample_count);
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Composite"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target.view,
+                resolve_target: target.resolve_target,
+                ops: wgpu::Operations {
+                    load: if target.clear { wgpu::LoadOp::Clear(wgpu::Color::BLACK) } else { wgpu::LoadOp::Load },
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &targets.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
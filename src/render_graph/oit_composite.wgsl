@@ -0,0 +1,27 @@
+// Blends the weighted average of the OIT accumulation target over the frame,
+// by how much the revealage target says is covered.
+#include "fullscreen.wgsl"
+
+@group(0) @binding(0) var accum_texture: texture_2d<f32>;
+@group(0) @binding(1) var reveal_texture: texture_2d<f32>;
+
+@vertex
+fn vert_main(@builtin(vertex_index) vertex_index: u32) -> FullscreenOutput {
+    return fullscreen_vertex(vertex_index);
+}
+
+@fragment
+fn frag_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.clip_position.xy);
+
+    let reveal = textureLoad(reveal_texture, coord, 0).r;
+    // Nothing transparent was drawn here
+    if reveal >= 1.0 {
+        discard;
+    }
+
+    let accum = textureLoad(accum_texture, coord, 0);
+    let average = accum.rgb / max(accum.a, 1e-5);
+
+    return vec4<f32>(average, 1.0 - reveal);
+}
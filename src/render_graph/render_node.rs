@@ -1,11 +1,122 @@
+use std::collections::{BTreeMap, HashMap};
+
+use encase::ShaderType;
+use log::warn;
+
 use crate::{Handle, MutHandle};
 use super::commands::{Command, DrawCommand};
+use super::BuildError;
+use super::run_policy::{RunPolicy, RunState};
 use crate::pipeline::Pipeline;
 
-use std::fs::File;
-use std::io::Read;
+use crate::pipeline::PipelineSettings;
+use crate::shader::{ProvidedBinding, ShaderPermutation};
 use crate::render_graph::{ResourceHandle, ResourceManager, ResourceType};
-use crate::types::{Instance, Uniform, UniformBuffer, UniformBufferType, UniformSet, Vertex};
+use crate::types::{BindSlot, DrawIndexedIndirectArgs, DrawIndirectArgs, IndirectArgs, Instance, InstanceBuffer, RawStorageBuffer, RawUniformBuffer, Transform, TransformHandle, TransformUniform, Uniform, UniformArena, UniformBuffer, UniformSet, Vertex};
+
+// The kinds of state a compiled command can set. Only the latest command of each
+// kind matters to a draw.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum StateKey {
+    Texture(u32),
+    Transient(usize), // Each transient read has its group to itself
+    UniformBlock,
+    PushConstants(u32, u32), // Stages, offset
+    DrawTransform,
+    Viewport,
+    Scissor,
+    StencilReference,
+}
+
+// State a sorted draw resets to how the pass starts when no command set it before
+// the draw, so it doesn't inherit what the draw replayed before it set
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DefaultState {
+    Viewport, // The node's viewport, or the whole target
+    Scissor, // The whole target
+    StencilReference, // 0
+    UniformBlock, // The arena's first block
+    PushConstants, // Zeroed, before the draw's own writes
+}
+
+// A draw and the state commands in effect when it was recorded, so draws can be
+// replayed in any order
+struct DrawItem {
+    defaults: Vec<DefaultState>,
+    state: Vec<usize>,
+    draw: usize,
+    // Index into draw_transforms, for the draw's position
+    transform: Option<usize>,
+}
+
+// A transient texture the node samples, in a group of its own. The bind group is
+// remade in prepare whenever the pool hands the node a different texture.
+struct TransientRead {
+    slot: BindSlot,
+    name: String,
+    layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bind_group: Option<(u64, wgpu::BindGroup)>, // Pooled texture id, bind group
+}
+
+// A uniform arena in a group of its own, and where each of its blocks landed this
+// frame. PushUniform blocks, DrawMesh transforms and the push constant fallback
+// all reach the shader this way.
+struct ArenaBinding {
+    name: &'static str,
+    group: u32,
+    arena: UniformArena,
+    offsets: Vec<Option<u32>>, // None for blocks too big for the arena
+}
+
+impl ArenaBinding {
+    fn new(name: &'static str, group: u32, arena: UniformArena) -> Self {
+        Self { name, group, arena, offsets: Vec::new() }
+    }
+
+    // Take the arena's group in the pipeline layout, unless something else already has it
+    fn claim<'a>(&'a self, groups: &mut BTreeMap<u32, (&'a wgpu::BindGroupLayout, String)>,
+                 provided: &mut Vec<ProvidedBinding>, errors: &mut Vec<BuildError>) {
+        if let Some((_, first)) = groups.get(&self.group) {
+            errors.push(BuildError::GroupConflict{ group: self.group, first: first.clone(), second: self.name.to_string() });
+            return;
+        }
+
+        groups.insert(self.group, (self.arena.get_bind_group_layout(), self.name.to_string()));
+        provided.extend(self.arena.layout_entries().iter()
+            .map(|entry| ProvidedBinding::from_layout_entry(self.group, entry)));
+    }
+
+    // Refill the arena with this frame's blocks, returning the indices of any that didn't fit
+    fn fill<I: IntoIterator<Item = Vec<u8>>>(&mut self, blocks: I) -> Vec<usize> {
+        self.arena.reset();
+        self.offsets = blocks.into_iter().map(|block| self.arena.push(&block)).collect();
+        self.arena.flush();
+
+        self.offsets.iter().enumerate().filter(|(_, offset)| offset.is_none()).map(|(index, _)| index).collect()
+    }
+
+    fn fits(&self, index: usize) -> bool {
+        self.offsets.get(index).is_some_and(|offset| offset.is_some())
+    }
+
+    fn bind<'a>(&'a self, index: usize, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(Some(offset)) = self.offsets.get(index) {
+            self.arena.bind(self.group, *offset, render_pass);
+        }
+    }
+}
+
+// Something a node reads, as shown by RenderGraph::to_dot. Uniform buffers have no
+// name, so they're told apart by address.
+pub(super) enum NodeResource {
+    Texture(String, BindSlot),
+    Mesh(String),
+    StorageBuffer(String, Option<BindSlot>), // No slot when used for indirect args
+    UniformBuffer(usize, BindSlot),
+    Transient(String, Option<BindSlot>), // No slot when the node renders into it
+}
 
 pub struct RenderNode {
     pub name: String,
@@ -15,28 +126,159 @@ pub struct RenderNode {
     compiled_commands: Vec<DrawCommand>,
 
     pipeline: Option<Pipeline>, // The pipeline that this node will use to render.
+    // A rebuild in `prepare` failed, so it's retried every frame until it succeeds
+    rebuild_pending: bool,
 
-    static_uniform_set: Option<UniformSet>,
-    dynamic_uniform_set: Option<UniformSet>,
+    // Uniform sets keyed by bind group index
+    uniform_sets: BTreeMap<u32, UniformSet>,
+
+    // Bind groups for the node's textures, built for the slot each BindTexture asked for
+    texture_bind_groups: HashMap<(u32, ResourceHandle), wgpu::BindGroup>,
+
+    // Transient textures the node samples, and the one it renders into instead of the frame
+    transient_reads: Vec<TransientRead>,
+    transient_target: Option<String>,
+
+    // The viewport the node starts each pass with (x, y, width, height)
+    viewport: Option<[f32; 4]>,
+
+    run_policy: RunPolicy,
+    run_state: RunState,
+
+    // Storage buffer bind groups, set once per pass
+    storage_bind_groups: Vec<(u32, wgpu::BindGroup)>,
+
+    // The uniform groups the pipeline layout was built with
+    built_uniform_groups: Vec<u32>,
+
+    // Per-draw uniforms: the arena, and the index of each compiled PushUniform in
+    // the node's commands
+    uniform_arena: Option<ArenaBinding>,
+    uniform_blocks: Vec<usize>,
+    // Blocks that outgrew the arena since the build, whose draws are skipped
+    oversized_blocks: Vec<usize>,
+
+    // Push constant ranges the pipeline declares, and the data from each SetPushConstants
+    push_constant_ranges: Vec<wgpu::PushConstantRange>,
+    push_constant_data: Vec<(wgpu::ShaderStages, u32, Vec<u8>)>,
+
+    // Without device support, push constants go through a small dynamic uniform at
+    // push_constant_fallback_slot instead, holding a zeroed block and then the whole
+    // push constant block as it stands after each SetPushConstants
+    push_constant_fallback_slot: BindSlot,
+    push_constant_fallback: Option<ArenaBinding>,
+    push_constant_blocks: Vec<Vec<u8>>,
+
+    // Per-draw model matrices from DrawMesh transforms, written into an arena at
+    // draw_transform_slot every frame (None draws get the identity)
+    draw_transform_slot: BindSlot,
+    draw_transforms: Vec<Option<TransformHandle>>,
+    draw_transform_arena: Option<ArenaBinding>,
+
+    // Bound to any group the shader skips, so the pipeline layout has no holes
+    empty_bind_group: Option<wgpu::BindGroup>,
+    empty_groups: Vec<u32>,
 
     // Configs
-    use_depth: bool,
+    // Depth testing, when set - otherwise on for transparent and OIT nodes only
+    use_depth: Option<bool>,
+    depth_only: bool,
+    use_vertex_layout: bool,
+
+    // What to clear the targets to. Unset values keep the default: the first
+    // node (at each sample count) clears to black and a depth of 1.0, later nodes load what's there.
+    clear_color: Option<wgpu::Color>,
+    clear_depth: Option<f32>,
+    clear_stencil: Option<u32>,
+
+    // MSAA sample count, or the renderer's when unset, and the count the pipeline was built for
+    sample_count: Option<u32>,
+    built_sample_count: u32,
+
+    stencil_state: Option<wgpu::StencilState>,
+
+    // Transparent nodes blend, don't write depth, and sort back to front
+    transparent: bool,
+    // Opaque nodes can sort front to back instead, for early-z
+    sort_front_to_back: bool,
+    draw_items: Vec<DrawItem>,
+    // The order to replay draw_items in this frame, when sorting
+    draw_order: Option<Vec<usize>>,
+    // Each DrawMeshInstanced's transforms and the node's own buffer for them, so
+    // nodes drawing the same mesh don't overwrite each other's (sorted) instances
+    instanced_transforms: Vec<(Vec<Transform>, InstanceBuffer)>,
+
+    // Draw with weighted blended order-independent transparency
+    oit: bool,
+    // The renderer's depth format when the pipeline was built
+    built_depth_format: wgpu::TextureFormat,
 
     _device: Handle<wgpu::Device>,
     _queue: Handle<wgpu::Queue>,
 }
 
 impl RenderNode {
+    // Where shaders find their push constants by default when the device doesn't
+    // support them: `@group(3) @binding(0) var<uniform>` in place of `var<push_constant>`.
+    // Shaders can tell which to declare with `#ifdef PUSH_CONSTANTS`.
+    pub const PUSH_CONSTANT_FALLBACK_GROUP: u32 = 3;
+
     pub fn new(name: String, _device: Handle<wgpu::Device>, _queue: Handle<wgpu::Queue>) -> Self {
         Self {
             name,
             commands: Vec::new(),
             compiled_commands: Vec::new(),
             pipeline: None,
-            static_uniform_set: None,
-            dynamic_uniform_set: None,
+            rebuild_pending: false,
+            uniform_sets: BTreeMap::new(),
+            texture_bind_groups: HashMap::new(),
+            transient_reads: Vec::new(),
+            transient_target: None,
+            viewport: None,
+
+            run_policy: RunPolicy::Always,
+            run_state: RunState::new(),
+            storage_bind_groups: Vec::new(),
+            built_uniform_groups: Vec::new(),
+
+            uniform_arena: None,
+            uniform_blocks: Vec::new(),
+            oversized_blocks: Vec::new(),
+
+            push_constant_ranges: Vec::new(),
+            push_constant_data: Vec::new(),
+            push_constant_fallback_slot: BindSlot::new(Self::PUSH_CONSTANT_FALLBACK_GROUP, 0),
+            push_constant_fallback: None,
+            push_constant_blocks: Vec::new(),
+
+            draw_transform_slot: BindSlot::new(2, 0),
+            draw_transforms: Vec::new(),
+            draw_transform_arena: None,
+
+            empty_bind_group: None,
+            empty_groups: Vec::new(),
 
-            use_depth: false,
+            use_depth: None,
+            depth_only: false,
+            use_vertex_layout: true,
+
+            clear_color: None,
+            clear_depth: None,
+            clear_stencil: None,
+
+            sample_count: None,
+            built_sample_count: 1,
+
+            stencil_state: None,
+
+            transparent: false,
+            sort_front_to_back: false,
+            draw_items: Vec::new(),
+            draw_order: None,
+            instanced_transforms: Vec::new(),
+
+            oit: false,
+            built_depth_format: wgpu::TextureFormat::Depth32Float,
 
             _device,
             _queue,
@@ -47,112 +289,537 @@ impl RenderNode {
         self.commands.push(command);
     }
 
+    // Drop every command, e.g. to record new ones and rebuild the node
+    pub fn clear_commands(&mut self) {
+        self.commands.clear();
+    }
+
+    // Test draws against the depth buffer, and write depth unless the node is
+    // transparent or OIT. Transparent and OIT nodes test depth unless told not to,
+    // so they're hidden behind opaque geometry drawn before them.
     pub fn use_depth(&mut self, use_depth: bool) {
-        self.use_depth = use_depth;
+        self.use_depth = Some(use_depth);
     }
 
-    pub fn add_uniform_buffer<T: Uniform>(&mut self, data: &T, buffer: UniformBufferType) -> Option<Handle<UniformBuffer>> {
-        let mut dynamic_uniform_buffer = None;
+    fn tests_depth(&self) -> bool {
+        self.use_depth.unwrap_or(self.transparent || self.oit)
+    }
 
-        match buffer {
-            UniformBufferType::STATIC => {
-                if let Some(static_uniform_set) = &mut self.static_uniform_set {
-                    let uniform_buffer = UniformBuffer::new(self._device.clone(), self._queue.clone(), data);
-                    static_uniform_set.add_uniform_buffer(&self._device, uniform_buffer);
-                } else {
-                    let uniform_buffer = UniformBuffer::new(self._device.clone(), self._queue.clone(), data);
-                    let uniform_buffer = Handle::new(uniform_buffer);
-                    let uniform_set = UniformSet::new(&self._device, vec![uniform_buffer]);
-                    self.static_uniform_set = Some(uniform_set);
+    // Only write depth - the shader is compiled with DEPTH_ONLY and has no fragment stage
+    pub fn depth_only(&mut self, depth_only: bool) {
+        self.depth_only = depth_only;
+    }
+
+    // Clear values clear the whole target, whatever the node's viewport. Nodes that
+    // share a target through viewports (template instances, say) should leave them
+    // unset: the first node drawing to the target clears it for all of them.
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = Some(color);
+    }
 
-                    dynamic_uniform_buffer = Some(self.static_uniform_set.as_ref().unwrap().uniform_buffers[0].clone());
+    pub fn set_clear_depth(&mut self, depth: f32) {
+        self.clear_depth = Some(depth);
+    }
 
-                }
+    // Only used when the depth format has a stencil aspect
+    pub fn set_clear_stencil(&mut self, stencil: u32) {
+        self.clear_stencil = Some(stencil);
+    }
+
+    // Stencil test and writes for the node's draws, with Command::SetStencilReference
+    // setting the reference. Needs a stencil depth format (see Renderer::set_depth_format).
+    pub fn set_stencil_state(&mut self, stencil: wgpu::StencilState) {
+        self.stencil_state = Some(stencil);
+    }
+
+    // Alpha blend, testing depth without writing it. Draws (and instances) are sorted back to
+    // front once the renderer has a sort camera (see Renderer::update_sort_camera).
+    pub fn transparent(&mut self, transparent: bool) {
+        self.transparent = transparent;
+    }
+
+    // Sort an opaque node's draws front to back once the renderer has a sort camera,
+    // so early-z rejects more. Off by default, as it changes the draw order.
+    pub fn sort_front_to_back(&mut self, sort: bool) {
+        self.sort_front_to_back = sort;
+    }
+
+    // Weighted blended order-independent transparency, for overlapping transparent
+    // geometry that sorting can't fix (particles, foliage). The shader is compiled with
+    // OIT and returns `oit_output(color, depth)` from oit.wgsl; draws are tested against
+    // the depth buffer without writing it, and composited over the frame after the last
+    // OIT node in a row.
+    pub fn order_independent_transparency(&mut self, oit: bool) {
+        self.oit = oit;
+    }
+
+    // Whether this node draws into the OIT targets this frame
+    pub(super) fn is_oit(&self) -> bool {
+        self.oit && self.pipeline.is_some()
+    }
+
+    // Transparent nodes sort back to front for correct blending, and nodes that ask
+    // for it front to back. Stencil nodes keep their command order, as their draws
+    // usually depend on what the ones before them wrote.
+    fn sorts_draws(&self) -> bool {
+        (self.transparent || self.sort_front_to_back) && self.stencil_state.is_none()
+    }
+
+    // Whether the node renders with a depth/stencil attachment
+    pub(super) fn uses_depth_attachment(&self) -> bool {
+        self.tests_depth() || self.depth_only || self.oit || self.stencil_state.is_some()
+    }
+
+    // Render into the transient texture `name` (see Renderer::declare_transient_texture)
+    // instead of the frame, for later nodes to sample with Command::BindTransient. The
+    // node gets a depth texture of the same size from the pool if it uses depth.
+    // Transient targets are single-sampled.
+    pub fn render_to_transient(&mut self, name: &str) {
+        self.transient_target = Some(name.to_string());
+    }
+
+    pub(super) fn get_transient_target(&self) -> Option<&str> {
+        self.transient_target.as_deref()
+    }
+
+    pub(super) fn set_transient_target(&mut self, name: Option<String>) {
+        self.transient_target = name;
+    }
+
+    // Run the node only some frames (see RunPolicy). Give it a transient target so
+    // later nodes can keep using its output on the frames it skips.
+    pub fn set_run_policy(&mut self, run_policy: RunPolicy) {
+        self.run_policy = run_policy;
+    }
+
+    pub fn get_run_policy(&self) -> &RunPolicy {
+        &self.run_policy
+    }
+
+    // Run an OnDemand node in the next frame
+    pub fn request_run(&mut self) {
+        self.run_state.request_run();
+    }
+
+    // Decide whether the node runs this frame, after the transient textures have
+    // been handed out
+    pub(super) fn update_run_state(&mut self, resource_manager: &ResourceManager) -> bool {
+        let target_id = self.transient_target.as_ref()
+            .and_then(|name| resource_manager.get_transient(name))
+            .map(|texture| texture.get_id());
+
+        self.run_state.update(&self.run_policy, target_id, |flag| resource_manager.is_render_flag_set(flag))
+    }
+
+    pub(super) fn runs_this_frame(&self) -> bool {
+        self.run_state.runs_this_frame()
+    }
+
+    // Start every pass drawing into part of the target, in pixels from the top left.
+    // SetViewport commands still override it for the draws after them.
+    pub fn set_viewport(&mut self, viewport: Option<[f32; 4]>) {
+        self.viewport = viewport;
+    }
+
+    pub fn get_viewport(&self) -> Option<[f32; 4]> {
+        self.viewport
+    }
+
+    pub(super) fn get_transient_reads(&self) -> Vec<&str> {
+        self.commands.iter().filter_map(|command| match command {
+            Command::BindTransient(_, name) => Some(name.as_str()),
+            _ => None,
+        }).collect()
+    }
+
+    // Transient targets are single-sampled, other nodes use their own count or the renderer's
+    fn target_sample_count(&self, resource_manager: &ResourceManager) -> u32 {
+        match self.transient_target {
+            Some(_) => 1,
+            None => self.sample_count.unwrap_or(resource_manager.get_sample_count()),
+        }
+    }
+
+    // Render this node with its own MSAA sample count instead of the renderer's.
+    // Every node drawing to the frame shares its multisampled color and depth, so
+    // they must all end up at the same count - the graph won't build otherwise.
+    pub fn set_sample_count(&mut self, count: u32) {
+        self.sample_count = Some(count);
+    }
+
+    // Whether the pipeline takes the default Vertex layout in buffer slot 0. Nodes
+    // that only use Command::Draw and make their own geometry can leave it out.
+    pub fn use_vertex_layout(&mut self, use_vertex_layout: bool) {
+        self.use_vertex_layout = use_vertex_layout;
+    }
+
+    // Create a uniform buffer bound at `slot`, returning a handle for updating it
+    pub fn add_uniform_buffer<T: Uniform>(&mut self, data: &T, slot: BindSlot) -> UniformBuffer<T> {
+        let uniform_buffer = UniformBuffer::new(self._device.clone(), self._queue.clone(), data);
+
+        self.add_uniform_buffer_handle(&uniform_buffer, slot);
+
+        uniform_buffer
+    }
+
+    // Add an existing uniform buffer to the node. The same buffer can sit in a
+    // different slot in every node that uses it.
+    pub fn add_uniform_buffer_handle<T: Uniform>(&mut self, buffer: &UniformBuffer<T>, slot: BindSlot) {
+        self.add_raw_uniform_buffer(buffer.get_raw(), slot);
+    }
+
+    pub(crate) fn add_raw_uniform_buffer(&mut self, buffer: Handle<RawUniformBuffer>, slot: BindSlot) {
+        if let Some(uniform_set) = self.uniform_sets.get_mut(&slot.group) {
+            if uniform_set.has_binding(slot.binding) {
+                warn!("Render node {}: replacing the uniform buffer at @group({}) @binding({})",
+                      self.name, slot.group, slot.binding);
             }
-            UniformBufferType::DYNAMIC => {
-                if let Some(dynamic_uniform_set) = &mut self.dynamic_uniform_set {
-                    let uniform_buffer = UniformBuffer::new(self._device.clone(), self._queue.clone(), data);
-                    dynamic_uniform_set.add_uniform_buffer(&self._device, uniform_buffer);
-                    dynamic_uniform_buffer = Some(dynamic_uniform_set.uniform_buffers[dynamic_uniform_set.uniform_buffers.len() - 1].clone());
-                } else {
-                    let uniform_buffer = UniformBuffer::new(self._device.clone(), self._queue.clone(), data);
-                    let uniform_buffer = Handle::new(uniform_buffer);
-                    let uniform_set = UniformSet::new(&self._device, vec![uniform_buffer]);
-                    self.dynamic_uniform_set = Some(uniform_set);
-                    dynamic_uniform_buffer = Some(self.dynamic_uniform_set.as_ref().unwrap().uniform_buffers[0].clone());
-                }
+
+            uniform_set.add_uniform_buffer(slot.binding, buffer);
+        } else {
+            self.uniform_sets.insert(slot.group, UniformSet::new(vec![(slot.binding, buffer)]));
+        }
+    }
+
+    // Give the node an arena of `block_size` byte blocks for PushUniform, bound at
+    // `slot` with a dynamic offset per draw. The arena takes the whole group.
+    pub fn add_uniform_arena(&mut self, slot: BindSlot, block_size: u64) {
+        let arena = UniformArena::new(self._device.clone(), self._queue.clone(), slot.binding, block_size, 64);
+        self.uniform_arena = Some(ArenaBinding::new("the uniform arena", slot.group, arena));
+    }
+
+    // Where DrawMesh transforms are bound, @group(2) @binding(0) by default. The
+    // shader declares it as `var<uniform> transform: Transform` under DRAW_TRANSFORM.
+    pub fn set_draw_transform_slot(&mut self, slot: BindSlot) {
+        self.draw_transform_slot = slot;
+    }
+
+    // Declare push constants visible to `stages` over `range` (in bytes). Set them
+    // with Command::SetPushConstants.
+    pub fn add_push_constant_range(&mut self, stages: wgpu::ShaderStages, range: std::ops::Range<u32>) {
+        self.push_constant_ranges.push(wgpu::PushConstantRange { stages, range });
+    }
+
+    // Where the push constant fallback uniform is bound, for shaders that use
+    // @group(3) for something else
+    pub fn set_push_constant_fallback_slot(&mut self, slot: BindSlot) {
+        self.push_constant_fallback_slot = slot;
+    }
+
+    fn uses_draw_transforms(&self) -> bool {
+        self.commands.iter().any(|command| matches!(command, Command::DrawMesh(_, Some(_))))
+    }
+
+    fn supports_push_constants(&self) -> bool {
+        self._device.features().contains(wgpu::Features::PUSH_CONSTANTS)
+    }
+
+    pub fn remove_uniform_buffer(&mut self, slot: BindSlot) {
+        if let Some(uniform_set) = self.uniform_sets.get_mut(&slot.group) {
+            uniform_set.remove_uniform_buffer(slot.binding);
+
+            if uniform_set.uniform_buffers.is_empty() {
+                self.uniform_sets.remove(&slot.group);
+            }
+        }
+    }
+
+    // Bring the uniform bind groups up to date before the frame is recorded. A
+    // uniform set whose layout changed since the last build (a buffer was added
+    // or removed, or resized) needs the pipeline rebuilt too.
+    pub(super) fn prepare(&mut self, resource_manager: MutHandle<ResourceManager>) -> Result<(), Vec<BuildError>> {
+        if self.pipeline.is_none() && !self.rebuild_pending {
+            return Ok(());
+        }
+
+        let mut layout_changed = self.rebuild_pending || self.uniform_sets.len() != self.built_uniform_groups.len()
+            || !self.uniform_sets.keys().eq(self.built_uniform_groups.iter());
+
+        // The renderer's sample count or depth format may have changed since the pipeline was built
+        {
+            let resource_manager = resource_manager.lock().unwrap();
+            layout_changed |= self.target_sample_count(&resource_manager) != self.built_sample_count;
+            layout_changed |= resource_manager.get_depth_format() != self.built_depth_format;
+        }
+
+        for uniform_set in self.uniform_sets.values_mut() {
+            layout_changed |= uniform_set.prepare(&self._device);
+        }
+
+        // The old pipeline can't be used with the new layouts, so a node that
+        // fails to rebuild stops drawing until a retry succeeds. The errors are
+        // only reported the first time.
+        if layout_changed {
+            if let Err(errors) = self.build_pipeline(resource_manager.clone()) {
+                self.pipeline = None;
+                let first_failure = !self.rebuild_pending;
+                self.rebuild_pending = true;
+                return if first_failure { Err(errors) } else { Ok(()) };
+            }
+        }
+
+        // Refill the per-draw uniforms for this frame
+        if let Some(arena) = &mut self.uniform_arena {
+            let oversized = arena.fill(self.uniform_blocks.iter().map(|index| match self.commands.get(*index) {
+                Some(Command::PushUniform(data)) => data.to_wgpu(),
+                _ => Vec::new(),
+            }));
+
+            // Only warn when a block first outgrows the arena, not every frame
+            if oversized != self.oversized_blocks && !oversized.is_empty() {
+                warn!("Render node {}: PushUniform blocks {:?} grew past the uniform arena's {} byte blocks, skipping their draws",
+                      self.name, oversized, arena.arena.block_size());
             }
+            self.oversized_blocks = oversized;
+        }
+
+        if let Some(arena) = &mut self.draw_transform_arena {
+            // Model matrices and push constant blocks have a fixed size, so they always fit
+            arena.fill(self.draw_transforms.iter().map(|transform| {
+                let model_matrix = match transform {
+                    Some(transform) => TransformUniform::new(&transform.get()),
+                    None => TransformUniform{ model_matrix: nalgebra::Matrix4::identity() },
+                };
+                model_matrix.to_wgpu()
+            }));
         }
 
-        dynamic_uniform_buffer
+        if let Some(arena) = &mut self.push_constant_fallback {
+            arena.fill(self.push_constant_blocks.iter().cloned());
+        }
+
+        self.sort_draws(&resource_manager.lock().unwrap());
+        self.prepare_transient_reads(&resource_manager.lock().unwrap());
+
+        Ok(())
     }
 
-    // Add an existing uniform buffer to the node
-    pub fn add_uniform_buffer_handle(&mut self, buffer: Handle<UniformBuffer>, buffer_type: UniformBufferType) {
-        match buffer_type {
-            UniformBufferType::STATIC => {
-                if let Some(static_uniform_set) = &mut self.static_uniform_set {
-                    static_uniform_set.add_existing_uniform_buffer(buffer.clone());
-                } else {
-                    let uniform_set = UniformSet::new(&self._device, vec![buffer.clone()]);
-                    self.static_uniform_set = Some(uniform_set);
-                }
+    // Point the node's transient reads at whichever pooled textures they got this frame
+    fn prepare_transient_reads(&mut self, resource_manager: &ResourceManager) {
+        for read in self.transient_reads.iter_mut() {
+            let Some(texture) = resource_manager.get_transient(&read.name) else {
+                read.bind_group = None;
+                continue;
+            };
+
+            if read.bind_group.as_ref().is_some_and(|(id, _)| *id == texture.get_id()) {
+                continue;
             }
-            UniformBufferType::DYNAMIC => {
-                if let Some(dynamic_uniform_set) = &mut self.dynamic_uniform_set {
-                    dynamic_uniform_set.add_existing_uniform_buffer(buffer.clone());
+
+            let bind_group = self._device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Render Node Transient Bind Group"),
+                layout: &read.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: read.slot.binding,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: read.slot.binding + 1,
+                        resource: wgpu::BindingResource::Sampler(&read.sampler),
+                    },
+                ],
+            });
+            read.bind_group = Some((texture.get_id(), bind_group));
+        }
+    }
+
+    // The layout and sampler for sampling a transient texture of `format` at `slot`
+    fn create_transient_read(&self, slot: BindSlot, name: &str, format: wgpu::TextureFormat) -> Result<TransientRead, BuildError> {
+        let sample_type = format.sample_type(None, Some(self._device.features()))
+            .ok_or_else(|| BuildError::Transient(format!("{} has a combined depth-stencil format and can't be sampled", name)))?;
+        let filterable = matches!(sample_type, wgpu::TextureSampleType::Float{ filterable: true });
+
+        let layout_entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: slot.binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: slot.binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(if filterable {
+                    wgpu::SamplerBindingType::Filtering
                 } else {
-                    let uniform_set = UniformSet::new(&self._device, vec![buffer.clone()]);
-                    self.dynamic_uniform_set = Some(uniform_set);
-                }
+                    wgpu::SamplerBindingType::NonFiltering
+                }),
+                count: None,
+            },
+        ];
+
+        let layout = self._device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Render Node Transient Bind Group Layout"),
+            entries: &layout_entries,
+        });
+
+        let filter = if filterable { wgpu::FilterMode::Linear } else { wgpu::FilterMode::Nearest };
+        let sampler = self._device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Transient Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+
+        Ok(TransientRead { slot, name: name.to_string(), layout_entries, layout, sampler, bind_group: None })
+    }
+
+    // Order this frame's draws (and a transparent node's instances) by distance to the sort camera
+    fn sort_draws(&mut self, resource_manager: &ResourceManager) {
+        let Some(view_position) = resource_manager.get_view_position() else {
+            return;
+        };
+
+        let distance = |position: &nalgebra::Vector3<f32>| (position - view_position).norm_squared();
+
+        if !self.draw_items.is_empty() {
+            // Draws without a transform count as being at the camera
+            let distances: Vec<f32> = self.draw_items.iter().map(|item| {
+                item.transform
+                    .and_then(|index| self.draw_transforms.get(index).cloned().flatten())
+                    .map(|transform| distance(&transform.get().position))
+                    .unwrap_or(0.0)
+            }).collect();
+
+            let mut order: Vec<usize> = (0..self.draw_items.len()).collect();
+            if self.transparent {
+                order.sort_by(|a, b| distances[*b].total_cmp(&distances[*a]));
+            } else {
+                order.sort_by(|a, b| distances[*a].total_cmp(&distances[*b]));
+            }
+
+            self.draw_order = Some(order);
+        }
+
+        if self.transparent && self.sorts_draws() {
+            for (transforms, instance_buffer) in self.instanced_transforms.iter_mut() {
+                let mut transforms = transforms.clone();
+                transforms.sort_by(|a, b| distance(&b.position).total_cmp(&distance(&a.position)));
+
+                instance_buffer.instances = transforms.iter().map(|transform| transform.to_instance()).collect();
+                instance_buffer.update(&self._queue);
             }
         }
     }
 
-    pub(super) fn build_pipeline(&mut self, resource_manager: MutHandle<ResourceManager>) {
+    pub(super) fn build_pipeline(&mut self, resource_manager: MutHandle<ResourceManager>) -> Result<(), Vec<BuildError>> {
         let mut resource_manager = resource_manager.lock().unwrap();
 
-        let mut shader_module = None;
-        let mut bind_group_layouts = Vec::new();
-        let mut vertex_buffer_layouts = vec![Vertex::desc()];
+        let mut vertex_buffer_layouts = if self.use_vertex_layout { vec![Vertex::desc()] } else { Vec::new() };
         let mut compiled_commands = Vec::new();
+        let mut texture_slots = Vec::new();
+        let mut storage_groups: BTreeMap<u32, Vec<(u32, String, Handle<RawStorageBuffer>)>> = BTreeMap::new();
+        let mut uniform_blocks = Vec::new();
+        let mut push_constant_data = Vec::new();
+        let mut draw_transforms = Vec::new();
+        let mut instanced_transforms = Vec::new();
+        let mut transient_reads = Vec::new();
+        let mut errors = Vec::new();
 
-        // Get our bind group layouts from our uniform sets
-        if let Some(static_uniform_set) = &self.static_uniform_set {
-            bind_group_layouts.push(&static_uniform_set.bind_group_layout);
+        if let Some(error) = self.viewport.and_then(|viewport| check_viewport(viewport, 0.0, 1.0)) {
+            errors.push(error);
         }
 
-        if let Some(dynamic_uniform_set) = &self.dynamic_uniform_set {
-            println!("Adding dynamic uniform set");
-            bind_group_layouts.push(&dynamic_uniform_set.bind_group_layout);
+        // Push constants end where the furthest range ends
+        let push_constant_size = self.push_constant_ranges.iter().map(|range| range.range.end).max().unwrap_or(0);
+        let native_push_constants = self.supports_push_constants();
+
+        let mut push_constant_fallback = None;
+        if native_push_constants {
+            let max_size = self._device.limits().max_push_constant_size;
+            if push_constant_size > max_size {
+                errors.push(BuildError::PushConstants(format!("ranges need {} bytes but the device allows {}", push_constant_size, max_size)));
+            }
+        } else if push_constant_size > 0 {
+            // Uniform structs are padded to 16 bytes
+            let block_size = (push_constant_size as u64).div_ceil(16) * 16;
+            let arena = UniformArena::new(self._device.clone(), self._queue.clone(),
+                                          self.push_constant_fallback_slot.binding, block_size, 16);
+            push_constant_fallback = Some(ArenaBinding::new("the push constant fallback", self.push_constant_fallback_slot.group, arena));
         }
 
+        // Pick the shader variant from the node's commands
+        let permutation = self.shader_permutation().ok_or_else(|| vec![BuildError::MissingShader])?;
+        let shader = resource_manager.load_shader(&permutation).map_err(|e| vec![e])?;
+
         // Load all textures and meshes
-        for command in self.commands.iter(){
+        for (command_index, command) in self.commands.iter().enumerate(){
             match command{
-                Command::LoadShader(shader) => {
-                    // Load the shader
-                    let mut file = File::open(shader).unwrap();
-                    let mut contents = String::new();
-                    file.read_to_string(&mut contents).unwrap();
-
-                    let module = self._device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                        label: Some(shader),
-                        source: wgpu::ShaderSource::Wgsl(contents.into()),
-                    });
-
-                    shader_module = Some(module);
-                }
-                Command::BindTexture(idx, texture_id) => {
+                Command::BindTexture(slot, texture_id) => {
                     let texture_handle = ResourceHandle::new(texture_id.clone(), ResourceType::Texture);
                     resource_manager.load_texture(texture_handle.clone(), texture_id.clone());
-                    compiled_commands.push(DrawCommand::BindTexture(*idx, texture_handle));
+                    texture_slots.push((*slot, texture_handle.clone()));
+                    compiled_commands.push(DrawCommand::BindTexture(slot.group, texture_handle));
+                }
+                Command::BindTransient(slot, name) => {
+                    let read = resource_manager.get_transient_descriptor(name)
+                        .ok_or_else(|| BuildError::MissingTransient(name.clone()))
+                        .and_then(|descriptor| self.create_transient_read(*slot, name, descriptor.format));
+
+                    match read {
+                        Ok(read) => {
+                            compiled_commands.push(DrawCommand::BindTransient(transient_reads.len()));
+                            transient_reads.push(read);
+                        }
+                        Err(error) => errors.push(error),
+                    }
+                }
+                Command::BindStorageBuffer(slot, name) => {
+                    let handle = ResourceHandle::new(name.clone(), ResourceType::StorageBuffer);
+                    match resource_manager.get_storage_buffer(handle) {
+                        Some(buffer) => storage_groups.entry(slot.group).or_default().push((slot.binding, name.clone(), buffer)),
+                        None => errors.push(BuildError::MissingStorageBuffer(name.clone())),
+                    }
+                }
+                Command::SetPushConstants(stages, offset, data) => {
+                    let end = offset + data.len() as u32;
+                    let covered = self.push_constant_ranges.iter()
+                        .any(|range| range.stages.contains(*stages) && range.range.start <= *offset && end <= range.range.end);
+
+                    if offset % 4 != 0 || data.len() % 4 != 0 {
+                        errors.push(BuildError::PushConstants(format!("offset {} and size {} must be multiples of 4", offset, data.len())));
+                    } else if !covered {
+                        errors.push(BuildError::PushConstants(format!("no {:?} range covers bytes {}..{}", stages, offset, end)));
+                    } else {
+                        compiled_commands.push(DrawCommand::SetPushConstants(push_constant_data.len()));
+                        push_constant_data.push((*stages, *offset, data.clone()));
+                    }
+                }
+                Command::PushUniform(data) => {
+                    let block = data.to_wgpu();
+
+                    match &self.uniform_arena {
+                        None => errors.push(BuildError::MissingUniformArena),
+                        Some(ArenaBinding{ arena, .. }) if block.len() as u64 > arena.block_size() => {
+                            errors.push(BuildError::UniformBlockTooLarge{ size: block.len() as u64, block_size: arena.block_size() });
+                        }
+                        Some(_) => {
+                            compiled_commands.push(DrawCommand::SetUniformBlock(uniform_blocks.len()));
+                            uniform_blocks.push(command_index);
+                        }
+                    }
                 }
-                Command::DrawMesh(mesh_id) => {
+                Command::DrawMesh(mesh_id, _) | Command::DrawMeshInstanced(mesh_id, _) if !self.use_vertex_layout => {
+                    errors.push(BuildError::MeshWithoutVertexLayout(mesh_id.clone()));
+                }
+                Command::DrawMesh(mesh_id, transform) => {
                     // Load the mesh
                     let mesh_handle = ResourceHandle::new(mesh_id.clone(), ResourceType::Mesh);
                     resource_manager.load_mesh(mesh_handle.clone(), mesh_id);
 
+                    if self.uses_draw_transforms() {
+                        compiled_commands.push(DrawCommand::SetDrawTransform(draw_transforms.len()));
+                        draw_transforms.push(transform.clone());
+                    }
+
                     compiled_commands.push(DrawCommand::DrawMesh(mesh_handle));
                 }
                 Command::DrawMeshInstanced(mesh_id, transform_instances) => {
@@ -170,75 +837,594 @@ impl RenderNode {
                     // Load the mesh
                     resource_manager.load_mesh(mesh_handle.clone(), mesh_id);
 
-                    let mesh = resource_manager.get_mesh_mut(mesh_handle.clone()).unwrap_or_else(
-                        || panic!("Mesh with id {} not found", mesh_id)
-                    );
+                    compiled_commands.push(DrawCommand::DrawMeshInstanced(mesh_handle, instanced_transforms.len()));
+                    instanced_transforms.push((transform_instances.clone(), instance_buffer));
+                }
+                Command::Draw{ .. } if self.use_vertex_layout => {
+                    errors.push(BuildError::VertexLayoutWithoutMesh);
+                }
+                Command::Draw{ vertices, instances } => {
+                    compiled_commands.push(DrawCommand::Draw(vertices.clone(), instances.clone()));
+                }
+                Command::SetViewport{ x, y, width, height, min_depth, max_depth } => {
+                    match check_viewport([*x, *y, *width, *height], *min_depth, *max_depth) {
+                        Some(error) => errors.push(error),
+                        None => compiled_commands.push(DrawCommand::SetViewport([*x, *y, *width, *height], *min_depth, *max_depth)),
+                    }
+                }
+                Command::SetScissor{ width, height, .. } if *width == 0 || *height == 0 => {
+                    errors.push(BuildError::Viewport(format!("scissor rect is {}x{}", width, height)));
+                }
+                Command::SetScissor{ x, y, width, height } => {
+                    compiled_commands.push(DrawCommand::SetScissor([*x, *y, *width, *height]));
+                }
+                Command::SetStencilReference(reference) => {
+                    compiled_commands.push(DrawCommand::SetStencilReference(*reference));
+                }
+                Command::DrawIndirect{ .. } if self.use_vertex_layout => {
+                    errors.push(BuildError::VertexLayoutWithoutMesh);
+                }
+                Command::DrawIndirect{ buffer, offset } => {
+                    let handle = ResourceHandle::new(buffer.clone(), ResourceType::StorageBuffer);
+                    match resource_manager.get_storage_buffer(handle) {
+                        Some(args) => match check_indirect_args(buffer, &args, *offset, DrawIndirectArgs::SIZE) {
+                            Some(error) => errors.push(error),
+                            None => compiled_commands.push(DrawCommand::DrawIndirect(args, *offset)),
+                        },
+                        None => errors.push(BuildError::MissingStorageBuffer(buffer.clone())),
+                    }
+                }
+                Command::DrawIndexedIndirect{ mesh, .. } |
+                Command::MultiDrawIndexedIndirect{ mesh, .. } if !self.use_vertex_layout => {
+                    errors.push(BuildError::MeshWithoutVertexLayout(mesh.clone()));
+                }
+                Command::DrawIndexedIndirect{ mesh, buffer, offset } |
+                Command::MultiDrawIndexedIndirect{ mesh, buffer, offset, .. } => {
+                    let count = match command {
+                        Command::MultiDrawIndexedIndirect{ count, .. } => *count,
+                        _ => 1,
+                    };
+
+                    let mesh_handle = ResourceHandle::new(mesh.clone(), ResourceType::Mesh);
+                    resource_manager.load_mesh(mesh_handle.clone(), mesh);
 
-                    mesh.set_instances(&self._device.clone(), instance_buffer);
+                    // Every submesh reads its own `count` args, one after another
+                    let submeshes = resource_manager.get_mesh(mesh_handle.clone()).map_or(0, |mesh| mesh.submesh_count());
+                    let size = submeshes as u64 * count as u64 * DrawIndexedIndirectArgs::SIZE;
 
-                    compiled_commands.push(DrawCommand::DrawMeshInstanced(mesh_handle));
+                    let handle = ResourceHandle::new(buffer.clone(), ResourceType::StorageBuffer);
+                    match resource_manager.get_storage_buffer(handle) {
+                        Some(args) => match check_indirect_args(buffer, &args, *offset, size) {
+                            Some(error) => errors.push(error),
+                            None => compiled_commands.push(DrawCommand::DrawIndexedIndirect(mesh_handle, args, *offset, count)),
+                        },
+                        None => errors.push(BuildError::MissingStorageBuffer(buffer.clone())),
+                    }
                 }
                 _ => {}
             }
         }
 
 
-        for command in compiled_commands.iter(){
-            match command{
-                DrawCommand::BindTexture(_, texture_handle) => {
-                    let texture = resource_manager.get_texture(texture_handle.clone());
-                    if let Some(texture) = texture {
-                        bind_group_layouts.push(texture.get_bind_group_layout());
-                    }
-                },
-                _ => {}
+        for uniform_set in self.uniform_sets.values_mut(){
+            uniform_set.prepare(&self._device);
+        }
+
+        // Place every resource set in its bind group
+        let mut groups: BTreeMap<u32, (&wgpu::BindGroupLayout, String)> = BTreeMap::new();
+        let mut provided = Vec::new();
+
+        for (group, uniform_set) in self.uniform_sets.iter(){
+            groups.insert(*group, (uniform_set.get_bind_group_layout(), "a uniform set".to_string()));
+            provided.extend(uniform_set.layout_entries().iter()
+                .map(|entry| ProvidedBinding::from_layout_entry(*group, entry)));
+        }
+
+        let draw_transform_arena = (!draw_transforms.is_empty()).then(|| {
+            let arena = UniformArena::new(self._device.clone(), self._queue.clone(), self.draw_transform_slot.binding,
+                                          TransformUniform::min_size().get(), 16);
+            ArenaBinding::new("the draw transforms", self.draw_transform_slot.group, arena)
+        });
+
+        for arena in [&self.uniform_arena, &push_constant_fallback, &draw_transform_arena].into_iter().flatten(){
+            arena.claim(&mut groups, &mut provided, &mut errors);
+        }
+
+        // Storage buffers share a group with each other, but not with anything else
+        let mut storage_layouts = Vec::new();
+        let mut storage_bind_groups = Vec::new();
+
+        for (group, buffers) in storage_groups.iter(){
+            if let Some((_, first)) = groups.get(group){
+                errors.push(BuildError::GroupConflict{ group: *group, first: first.clone(), second: format!("storage buffer {}", buffers[0].1) });
+                continue;
+            }
+
+            let mut duplicate = false;
+            for (i, (binding, name, _)) in buffers.iter().enumerate(){
+                if let Some((_, first, _)) = buffers[..i].iter().find(|(b, _, _)| b == binding){
+                    errors.push(BuildError::GroupConflict{
+                        group: *group,
+                        first: format!("storage buffer {} at @binding({})", first, binding),
+                        second: format!("storage buffer {}", name),
+                    });
+                    duplicate = true;
+                }
+            }
+
+            if duplicate{
+                continue;
+            }
+
+            let layout_entries: Vec<wgpu::BindGroupLayoutEntry> = buffers.iter()
+                .map(|(binding, _, buffer)| buffer.layout_entry(*binding))
+                .collect();
+
+            let layout = self._device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Render Node Storage Bind Group Layout"),
+                entries: &layout_entries,
+            });
+
+            let entries: Vec<wgpu::BindGroupEntry> = buffers.iter().map(|(binding, _, buffer)| wgpu::BindGroupEntry {
+                binding: *binding,
+                resource: buffer.buffer.as_entire_binding(),
+            }).collect();
+
+            storage_bind_groups.push((*group, self._device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Render Node Storage Bind Group"),
+                layout: &layout,
+                entries: &entries,
+            })));
+
+            provided.extend(layout_entries.iter().map(|entry| ProvidedBinding::from_layout_entry(*group, entry)));
+            storage_layouts.push((*group, layout));
+        }
+
+        for (group, layout) in storage_layouts.iter(){
+            groups.insert(*group, (layout, "storage buffers".to_string()));
+        }
+
+        // Textures own their group. Several textures can share one, they're bound
+        // in turn between draws, so the first texture in a group decides its layout.
+        let mut texture_layouts: BTreeMap<u32, (BindSlot, wgpu::BindGroupLayout)> = BTreeMap::new();
+        let mut texture_bind_groups = HashMap::new();
+
+        for (slot, texture_handle) in texture_slots.iter(){
+            let Some(texture) = resource_manager.get_texture(texture_handle.clone()) else { continue };
+            let texture_name = format!("texture {}", texture_handle.get_id());
+
+            if let Some((_, first)) = groups.get(&slot.group){
+                errors.push(BuildError::GroupConflict{ group: slot.group, first: first.clone(), second: texture_name });
+                continue;
+            }
+
+            // Shift the texture's view/sampler bindings to start at the requested binding
+            let layout_entries: Vec<wgpu::BindGroupLayoutEntry> = texture.get_bind_group_layout_entries().iter()
+                .map(|entry| wgpu::BindGroupLayoutEntry{ binding: entry.binding + slot.binding, ..*entry })
+                .collect();
+
+            match texture_layouts.get(&slot.group){
+                Some((first_slot, _)) if first_slot != slot => {
+                    errors.push(BuildError::GroupConflict{
+                        group: slot.group,
+                        first: format!("a texture at @binding({})", first_slot.binding),
+                        second: format!("{} at @binding({})", texture_name, slot.binding),
+                    });
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    let layout = self._device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some("Render Node Texture Bind Group Layout"),
+                        entries: &layout_entries,
+                    });
+                    provided.extend(layout_entries.iter().map(|entry| ProvidedBinding::from_layout_entry(slot.group, entry)));
+                    texture_layouts.insert(slot.group, (*slot, layout));
+                }
             }
+
+            let (_, layout) = &texture_layouts[&slot.group];
+            let bind_group = self._device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Render Node Texture Bind Group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: slot.binding,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: slot.binding + 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+            });
+            texture_bind_groups.insert((slot.group, texture_handle.clone()), bind_group);
+        }
+
+        for (group, (_, layout)) in texture_layouts.iter(){
+            groups.insert(*group, (layout, "a texture".to_string()));
+        }
+
+        // Transient reads own their group, like storage buffers
+        for read in transient_reads.iter(){
+            let name = format!("transient texture {}", read.name);
+            if let Some((_, first)) = groups.get(&read.slot.group){
+                errors.push(BuildError::GroupConflict{ group: read.slot.group, first: first.clone(), second: name });
+                continue;
+            }
+
+            provided.extend(read.layout_entries.iter().map(|entry| ProvidedBinding::from_layout_entry(read.slot.group, entry)));
+            groups.insert(read.slot.group, (&read.layout, name));
+        }
+
+        // Check everything against what the shader actually declares
+        errors.extend(shader.reflection.check_bindings(&provided).into_iter().map(BuildError::Binding));
+
+        let sample_count = self.target_sample_count(&resource_manager);
+        if !resource_manager.is_sample_count_supported(sample_count) {
+            errors.push(BuildError::UnsupportedSampleCount{ count: sample_count, supported: resource_manager.get_supported_sample_counts().to_vec() });
+        } else if self.oit && !resource_manager.get_oit_sample_counts().contains(&sample_count) {
+            // The OIT targets have formats of their own, which may support fewer counts
+            errors.push(BuildError::UnsupportedSampleCount{ count: sample_count, supported: resource_manager.get_oit_sample_counts().to_vec() });
+        }
+
+        // Nodes rendering to a transient texture draw in its format
+        let mut color_format = resource_manager.get_color_format();
+        if let Some(name) = &self.transient_target {
+            match resource_manager.get_transient_descriptor(name) {
+                Some(descriptor) if descriptor.format.is_depth_stencil_format() => {
+                    errors.push(BuildError::Transient(format!("{} has a depth format and can't be a color target", name)));
+                }
+                Some(descriptor) => color_format = descriptor.format,
+                None => errors.push(BuildError::MissingTransient(name.clone())),
+            }
+
+            if self.oit {
+                errors.push(BuildError::Transient(format!("OIT nodes composite into the frame and can't render to {}", name)));
+            }
+            if self.get_transient_reads().contains(&name.as_str()) {
+                errors.push(BuildError::Transient(format!("{} is both rendered to and sampled", name)));
+            }
+        }
+
+        let depth_format = resource_manager.get_depth_format();
+        if self.stencil_state.is_some() && !depth_format.has_stencil_aspect() {
+            errors.push(BuildError::NoStencilAspect(depth_format));
+        }
+
+        if !errors.is_empty(){
+            return Err(errors);
+        }
+
+        // Any group the pipeline layout skips over still needs a layout, and an
+        // (empty) bind group set at draw time
+        let group_count = groups.keys().copied().chain(shader.reflection.max_group())
+            .max().map_or(0, |max| max + 1);
+
+        let empty_layout = self._device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Empty Bind Group Layout"),
+            entries: &[],
+        });
+
+        let empty_groups: Vec<u32> = (0..group_count).filter(|group| !groups.contains_key(group)).collect();
+
+        let bind_group_layouts: Vec<&wgpu::BindGroupLayout> = (0..group_count)
+            .map(|group| groups.get(&group).map_or(&empty_layout, |(layout, _)| *layout))
+            .collect();
+
+        self.empty_bind_group = if empty_groups.is_empty() {
+            None
+        } else {
+            Some(self._device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Empty Bind Group"),
+                layout: &empty_layout,
+                entries: &[],
+            }))
+        };
+        self.empty_groups = empty_groups;
+
+        let mut pipeline_settings = PipelineSettings::default()
+            .color_format(color_format)
+            .depth_format(depth_format)
+            .sample_count(sample_count);
+
+        if let Some(stencil) = &self.stencil_state{
+            pipeline_settings = pipeline_settings.stencil(stencil.clone());
         }
 
-        let pipeline = Pipeline::new(self._device.clone(), shader_module.unwrap(),
-                                     bind_group_layouts, vertex_buffer_layouts, self.use_depth);
+        if self.transparent{
+            pipeline_settings = pipeline_settings.enable_transparency();
+        }
+
+        if self.oit{
+            pipeline_settings = pipeline_settings.enable_oit();
+        }
+
+        if native_push_constants{
+            pipeline_settings = pipeline_settings.push_constant_ranges(self.push_constant_ranges.clone());
+        }
+
+        if self.depth_only{
+            pipeline_settings = pipeline_settings.enable_depth_only();
+        }else if self.tests_depth(){
+            pipeline_settings = pipeline_settings.enable_depth_stencil();
+        }
+
+        let pipeline = Pipeline::new(self._device.clone(), &shader.module,
+                                     bind_group_layouts, vertex_buffer_layouts, pipeline_settings)
+            .map_err(|e| vec![BuildError::Pipeline(e.to_string())])?;
 
         self.pipeline = Some(pipeline);
+        self.rebuild_pending = false;
+        // Run again with the new pipeline, whatever the run policy
+        self.run_state.restart();
         self.compiled_commands = compiled_commands;
+        self.built_sample_count = sample_count;
+        self.built_depth_format = depth_format;
+        self.texture_bind_groups = texture_bind_groups;
+        self.transient_reads = transient_reads;
+        self.built_uniform_groups = self.uniform_sets.keys().copied().collect();
+        self.uniform_blocks = uniform_blocks;
+
+        // The fallback gets a zeroed block, then the whole block as it stands after each command
+        let mut block = vec![0u8; push_constant_size as usize];
+        self.push_constant_blocks = std::iter::once(block.clone()).chain(push_constant_data.iter().map(|(_, offset, data)| {
+            block[*offset as usize..*offset as usize + data.len()].copy_from_slice(data);
+            block.clone()
+        })).collect();
+        self.push_constant_data = push_constant_data;
+        self.push_constant_fallback = push_constant_fallback;
+        self.draw_transform_arena = draw_transform_arena;
+        self.draw_transforms = draw_transforms;
+        self.storage_bind_groups = storage_bind_groups;
+        self.draw_items = if self.sorts_draws() { Self::collect_draw_items(&self.compiled_commands, &self.push_constant_data) } else { Vec::new() };
+        self.draw_order = None;
+        self.instanced_transforms = instanced_transforms;
+
+        Ok(())
+    }
+
+    // Split the compiled commands into draws, each with the state it was recorded under
+    fn collect_draw_items(compiled_commands: &[DrawCommand], push_constant_data: &[(wgpu::ShaderStages, u32, Vec<u8>)]) -> Vec<DrawItem> {
+        let mut state: HashMap<StateKey, usize> = HashMap::new();
+        let mut draw_items = Vec::new();
+
+        for (index, command) in compiled_commands.iter().enumerate() {
+            let key = match command {
+                DrawCommand::BindTexture(group, _) => StateKey::Texture(*group),
+                DrawCommand::BindTransient(index) => StateKey::Transient(*index),
+                DrawCommand::SetUniformBlock(_) => StateKey::UniformBlock,
+                DrawCommand::SetDrawTransform(_) => StateKey::DrawTransform,
+                DrawCommand::SetViewport(..) => StateKey::Viewport,
+                DrawCommand::SetScissor(_) => StateKey::Scissor,
+                DrawCommand::SetStencilReference(_) => StateKey::StencilReference,
+                DrawCommand::SetPushConstants(data_index) => {
+                    // Keyed by where they're written, so earlier writes to other ranges are kept
+                    let (stages, offset, _) = &push_constant_data[*data_index];
+                    StateKey::PushConstants(stages.bits(), *offset)
+                }
+                _ => {
+                    let transform = match (command, state.get(&StateKey::DrawTransform)) {
+                        (DrawCommand::DrawMesh(_), Some(&transform_index)) => match compiled_commands[transform_index] {
+                            DrawCommand::SetDrawTransform(transform) => Some(transform),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+
+                    let defaults = [
+                        (StateKey::Viewport, DefaultState::Viewport),
+                        (StateKey::Scissor, DefaultState::Scissor),
+                        (StateKey::StencilReference, DefaultState::StencilReference),
+                        (StateKey::UniformBlock, DefaultState::UniformBlock),
+                    ].into_iter()
+                        .filter(|(key, _)| !state.contains_key(key))
+                        .map(|(_, default)| default)
+                        // Ranges the draw doesn't write may hold another draw's data
+                        .chain(std::iter::once(DefaultState::PushConstants))
+                        .collect();
+
+                    // Replayed in recorded order, so later push constants land on top
+                    let mut draw_state: Vec<usize> = state.values().copied().collect();
+                    draw_state.sort_unstable();
+
+                    draw_items.push(DrawItem{ defaults, state: draw_state, draw: index, transform });
+                    continue;
+                }
+            };
+
+            state.insert(key, index);
+        }
+
+        draw_items
+    }
+
+    // The shader variant is keyed by the defines this node's commands need
+    pub(super) fn shader_permutation(&self) -> Option<ShaderPermutation> {
+        let mut permutation = None;
+        let mut defines = Vec::new();
+
+        for command in self.commands.iter(){
+            match command{
+                Command::LoadShader(shader) => permutation = Some(ShaderPermutation::new(shader.clone())),
+                Command::Define(define) => defines.push(define.as_str()),
+                Command::BindTexture(..) => defines.push(ShaderPermutation::TEXTURED),
+                Command::DrawMeshInstanced(..) => defines.push(ShaderPermutation::INSTANCED),
+                _ => {}
+            }
+        }
+
+        if self.depth_only{
+            defines.push(ShaderPermutation::DEPTH_ONLY);
+        }
+
+        if self.uses_draw_transforms(){
+            defines.push(ShaderPermutation::DRAW_TRANSFORM);
+        }
+
+        if self.oit{
+            defines.push(ShaderPermutation::OIT);
+        }
+
+        if !self.push_constant_ranges.is_empty() && self.supports_push_constants(){
+            defines.push(ShaderPermutation::PUSH_CONSTANTS);
+        }
+
+        permutation.map(|mut permutation| {
+            for define in defines{
+                permutation.define(define);
+            }
+            permutation
+        })
     }
 
-    pub(super) fn execute(&self, id: usize, texture_view: &wgpu::TextureView,
+    // The textures, meshes and buffers this node's commands and uniform sets use
+    pub(super) fn get_resources(&self) -> Vec<NodeResource> {
+        let mut resources = Vec::new();
+
+        for (group, uniform_set) in self.uniform_sets.iter(){
+            for (binding, buffer) in uniform_set.uniform_buffers.iter(){
+                let address = &**buffer as *const RawUniformBuffer as usize;
+                resources.push(NodeResource::UniformBuffer(address, BindSlot::new(*group, *binding)));
+            }
+        }
+
+        if let Some(name) = &self.transient_target{
+            resources.push(NodeResource::Transient(name.clone(), None));
+        }
+
+        for command in self.commands.iter(){
+            match command{
+                Command::BindTexture(slot, path) => resources.push(NodeResource::Texture(path.clone(), *slot)),
+                Command::BindTransient(slot, name) => resources.push(NodeResource::Transient(name.clone(), Some(*slot))),
+                Command::BindStorageBuffer(slot, name) => resources.push(NodeResource::StorageBuffer(name.clone(), Some(*slot))),
+                Command::DrawMesh(path, _) | Command::DrawMeshInstanced(path, _) => resources.push(NodeResource::Mesh(path.clone())),
+                Command::DrawIndirect{ buffer, .. } => resources.push(NodeResource::StorageBuffer(buffer.clone(), None)),
+                Command::DrawIndexedIndirect{ mesh, buffer, .. } | Command::MultiDrawIndexedIndirect{ mesh, buffer, .. } => {
+                    resources.push(NodeResource::Mesh(mesh.clone()));
+                    resources.push(NodeResource::StorageBuffer(buffer.clone(), None));
+                }
+                _ => {}
+            }
+        }
+
+        resources
+    }
+
+    // The MSAA sample count the pipeline was built for
+    pub(super) fn get_sample_count(&self) -> u32 {
+        self.built_sample_count
+    }
+
+    pub(super) fn is_built(&self) -> bool {
+        self.pipeline.is_some()
+    }
+
+    // `clear_color` and `clear_depth` are set for the first node to use the color and
+    // depth/stencil targets this frame, `clear_oit` for the first OIT node in a row
+    pub(super) fn execute(&self, clear_color: bool, clear_depth: bool, clear_oit: bool, texture_view: &wgpu::TextureView,
                           resource_manager: MutHandle<ResourceManager>, encoder: &mut wgpu::CommandEncoder) {
         if let Some(pipeline) = &self.pipeline {
 
             let mut resource_manager = resource_manager.lock().unwrap();
 
-            let depth_texture = resource_manager.load_depth_texture();
+            let sample_count = self.built_sample_count;
 
+            let depth_texture = resource_manager.load_depth_texture(sample_count);
             let depth_texture = depth_texture.lock().unwrap();
 
+            // With MSAA we draw into a multisampled target and resolve into the real one
+            let msaa_target = (sample_count > 1).then(|| resource_manager.load_msaa_target(sample_count));
+            let msaa_target = msaa_target.as_ref().map(|target| target.lock().unwrap());
+            let (color_view, resolve_target) = match &msaa_target {
+                Some(msaa_target) => (&msaa_target.view, Some(texture_view)),
+                None => (texture_view, None),
+            };
+
+            let oit_targets = self.oit.then(|| resource_manager.load_oit_targets(sample_count));
+            let oit_targets = oit_targets.as_ref().map(|targets| targets.lock().unwrap());
+
+            let resource_manager: &ResourceManager = &resource_manager;
+
+            // Nodes rendering to a transient texture draw into it (and its depth
+            // texture) from the pool, in place of the frame
+            let transient = match &self.transient_target {
+                Some(name) => {
+                    let Some(color) = resource_manager.get_transient(name) else {
+                        warn!("Render node {}: transient texture {} has no texture this frame", self.name, name);
+                        return;
+                    };
+                    Some((color, resource_manager.get_transient(&ResourceManager::transient_depth_name(name))))
+                }
+                None => None,
+            };
+
+            let (color_view, resolve_target) = match transient {
+                Some((color, _)) => (&color.view, None),
+                None => (color_view, resolve_target),
+            };
+            let depth_view = match transient {
+                Some((_, Some(depth))) => &depth.view,
+                _ => &depth_texture.view,
+            };
+
+            // The depth texture always matches the surface, so it gives us the target size
+            let target_size = match transient {
+                Some((color, _)) => color.get_size(),
+                None => depth_texture.get_texture_size(),
+            };
+
+            // Without explicit clear values, only clear if we're the first node to use these targets this frame
+            let color_load = match self.clear_color {
+                Some(color) => wgpu::LoadOp::Clear(color),
+                None if clear_color => wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                None => wgpu::LoadOp::Load,
+            };
+            let depth_load = match self.clear_depth {
+                Some(depth) => wgpu::LoadOp::Clear(depth),
+                None if clear_depth => wgpu::LoadOp::Clear(1.0),
+                None => wgpu::LoadOp::Load,
+            };
+            let stencil_ops = if depth_texture.texture.format().has_stencil_aspect() {
+                Some(wgpu::Operations {
+                    load: match self.clear_stencil {
+                        Some(stencil) => wgpu::LoadOp::Clear(stencil),
+                        None if clear_depth => wgpu::LoadOp::Clear(0),
+                        None => wgpu::LoadOp::Load,
+                    },
+                    store: wgpu::StoreOp::Store,
+                })
+            } else {
+                None
+            };
+
+            let color_attachments = [Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: color_load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })];
+
+            let oit_attachments = oit_targets.as_ref().map(|targets| targets.color_attachments(clear_oit));
+
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some(&self.name),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        // Only clear if we're the first node in the render graph
-                        load: if id == 0 {
-                            wgpu::LoadOp::Clear(wgpu::Color::BLACK)
-                        } else {
-                            wgpu::LoadOp::Load
-                        },
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: if self.use_depth {
+                // Depth-only nodes have no color target, OIT nodes draw into the OIT targets
+                color_attachments: match &oit_attachments {
+                    _ if self.depth_only => &[],
+                    Some(oit_attachments) => oit_attachments,
+                    None => &color_attachments,
+                },
+                depth_stencil_attachment: if self.uses_depth_attachment() {
                     Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: &depth_texture.view,
+                        view: depth_view,
                         depth_ops: Some(wgpu::Operations {
-                            load: if id == 0 {
-                                wgpu::LoadOp::Clear(1.0)
-                            } else {
-                                wgpu::LoadOp::Load
-                            },
+                            load: depth_load,
                             store: wgpu::StoreOp::Store,
                         }),
-                        stencil_ops: None,
+                        stencil_ops,
                     })
                 } else {
                     None
@@ -248,48 +1434,328 @@ impl RenderNode {
 
             pipeline.bind_pipeline(&mut render_pass);
 
-            // If either the static or dynamic uniform set is not None, bind them
+            if let Some(viewport) = self.viewport {
+                Self::set_viewport_clamped(&mut render_pass, viewport, 0.0, 1.0, target_size);
+            }
 
-            // 0 is reserved for the projection matrix + view + model matrix
-            let using_static_uniform_set = self.static_uniform_set.is_some();
+            for (group, uniform_set) in self.uniform_sets.iter() {
+                uniform_set.bind(*group, &mut render_pass);
+            }
 
-            if let Some(static_uniform_set) = &self.static_uniform_set {
-                static_uniform_set.bind(0, &mut render_pass);
+            for (group, bind_group) in self.storage_bind_groups.iter() {
+                render_pass.set_bind_group(*group, bind_group, &[]);
             }
 
-            if let Some(dynamic_uniform_set) = &self.dynamic_uniform_set {
-                dynamic_uniform_set.bind(
-                    if using_static_uniform_set { 1 } else { 0 }, 
-                    &mut render_pass);
+            // Bind the first block up front so draws before any PushUniform or
+            // SetPushConstants still have one
+            for arena in [&self.uniform_arena, &self.push_constant_fallback].into_iter().flatten() {
+                arena.bind(0, &mut render_pass);
             }
 
+            if let Some(empty_bind_group) = &self.empty_bind_group {
+                for group in self.empty_groups.iter() {
+                    render_pass.set_bind_group(*group, empty_bind_group, &[]);
+                }
+            }
 
-            for command in self.compiled_commands.iter() {
-                match command {
-                    DrawCommand::DrawMesh(mesh_id) => {
-                        let mesh = resource_manager.get_mesh(mesh_id.clone());
 
-                        if let Some(mesh) = mesh {
-                            mesh.render(&mut render_pass);
+            // Sorted nodes replay each draw's state before it, in the order chosen in prepare
+            match &self.draw_order {
+                Some(order) => {
+                    for item in order.iter().map(|index| &self.draw_items[*index]) {
+                        let blocked = item.state.iter().any(|state| !self.uniform_block_fits(&self.compiled_commands[*state]));
+                        if blocked {
+                            continue;
                         }
-                    }
-                    DrawCommand::DrawMeshInstanced(mesh_id) => {
-                        let mesh = resource_manager.get_mesh(mesh_id.clone());
 
-                        if let Some(mesh) = mesh {
-                            mesh.render_instanced(&mut render_pass);
+                        for default in item.defaults.iter() {
+                            self.reset_state(*default, &mut render_pass, target_size);
+                        }
+                        for state in item.state.iter() {
+                            self.execute_command(&self.compiled_commands[*state], &mut render_pass, resource_manager, target_size);
                         }
+                        self.execute_command(&self.compiled_commands[item.draw], &mut render_pass, resource_manager, target_size);
                     }
-                    DrawCommand::BindTexture(index, texture_id) => {
-                        let texture = resource_manager.get_texture(texture_id.clone());
-
-                        if let Some(texture) = texture {
-                            texture.bind(*index, &mut render_pass);
+                }
+                None => {
+                    // Draws after a block that outgrew the arena are skipped until the next PushUniform
+                    let mut blocked = false;
+                    for command in self.compiled_commands.iter() {
+                        if let DrawCommand::SetUniformBlock(_) = command {
+                            blocked = !self.uniform_block_fits(command);
+                        }
+                        if blocked && command.is_draw() {
+                            continue;
                         }
+                        self.execute_command(command, &mut render_pass, resource_manager, target_size);
                     }
-                    _ => {}
                 }
             }
         }
     }
+
+    fn set_viewport_clamped(render_pass: &mut wgpu::RenderPass, [x, y, width, height]: [f32; 4],
+                            min_depth: f32, max_depth: f32, target_size: wgpu::Extent3d) {
+        // wgpu rejects viewports outside the target, which can happen for a frame after a resize
+        let x = x.clamp(0.0, target_size.width as f32);
+        let y = y.clamp(0.0, target_size.height as f32);
+        let width = width.min(target_size.width as f32 - x);
+        let height = height.min(target_size.height as f32 - y);
+
+        // Nothing of it is left on the target - wgpu rejects empty viewports, so keep the last one
+        if width <= 0.0 || height <= 0.0 {
+            return;
+        }
+
+        render_pass.set_viewport(x, y, width, height, min_depth, max_depth);
+    }
+
+    // False for a SetUniformBlock whose block outgrew the arena this frame
+    fn uniform_block_fits(&self, command: &DrawCommand) -> bool {
+        match (command, &self.uniform_arena) {
+            (DrawCommand::SetUniformBlock(index), Some(arena)) => arena.fits(*index),
+            _ => true,
+        }
+    }
+
+    fn reset_state<'a>(&'a self, default: DefaultState, render_pass: &mut wgpu::RenderPass<'a>, target_size: wgpu::Extent3d) {
+        match default {
+            DefaultState::Viewport => {
+                let viewport = self.viewport.unwrap_or([0.0, 0.0, target_size.width as f32, target_size.height as f32]);
+                Self::set_viewport_clamped(render_pass, viewport, 0.0, 1.0, target_size);
+            }
+            DefaultState::Scissor => render_pass.set_scissor_rect(0, 0, target_size.width, target_size.height),
+            DefaultState::StencilReference => render_pass.set_stencil_reference(0),
+            DefaultState::UniformBlock => {
+                if let Some(arena) = &self.uniform_arena {
+                    arena.bind(0, render_pass);
+                }
+            }
+            DefaultState::PushConstants => {
+                if let Some(arena) = &self.push_constant_fallback {
+                    arena.bind(0, render_pass);
+                } else {
+                    for range in self.push_constant_ranges.iter() {
+                        let zeros = vec![0u8; (range.range.end - range.range.start) as usize];
+                        render_pass.set_push_constants(range.stages, range.range.start, &zeros);
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute_command<'a>(&'a self, command: &'a DrawCommand, render_pass: &mut wgpu::RenderPass<'a>,
+                           resource_manager: &'a ResourceManager, target_size: wgpu::Extent3d) {
+        match command {
+            DrawCommand::BindTransient(index) => {
+                let read = &self.transient_reads[*index];
+                if let Some((_, bind_group)) = &read.bind_group {
+                    render_pass.set_bind_group(read.slot.group, bind_group, &[]);
+                }
+            }
+            DrawCommand::DrawMesh(mesh_id) => {
+                let mesh = resource_manager.get_mesh(mesh_id.clone());
+
+                if let Some(mesh) = mesh {
+                    mesh.render(render_pass);
+                }
+            }
+            DrawCommand::DrawMeshInstanced(mesh_id, index) => {
+                let mesh = resource_manager.get_mesh(mesh_id.clone());
+
+                if let (Some(mesh), Some((_, instance_buffer))) = (mesh, self.instanced_transforms.get(*index)) {
+                    mesh.render_instanced(render_pass, instance_buffer);
+                }
+            }
+            DrawCommand::Draw(vertices, instances) => {
+                render_pass.draw(vertices.clone(), instances.clone());
+            }
+            DrawCommand::SetViewport(viewport, min_depth, max_depth) => {
+                Self::set_viewport_clamped(render_pass, *viewport, *min_depth, *max_depth, target_size);
+            }
+            DrawCommand::SetScissor([x, y, width, height]) => {
+                let x = (*x).min(target_size.width);
+                let y = (*y).min(target_size.height);
+                let width = (*width).min(target_size.width - x);
+                let height = (*height).min(target_size.height - y);
+
+                // Entirely off the target, like an empty viewport
+                if width > 0 && height > 0 {
+                    render_pass.set_scissor_rect(x, y, width, height);
+                }
+            }
+            DrawCommand::SetStencilReference(reference) => {
+                render_pass.set_stencil_reference(*reference);
+            }
+            DrawCommand::DrawIndirect(args, offset) => {
+                render_pass.draw_indirect(&args.buffer, *offset);
+            }
+            DrawCommand::DrawIndexedIndirect(mesh_id, args, offset, count) => {
+                let multi_draw = *count > 1 && self._device.features().contains(wgpu::Features::MULTI_DRAW_INDIRECT);
+
+                if let Some(mesh) = resource_manager.get_mesh(mesh_id.clone()) {
+                    mesh.render_indexed_indirect(render_pass, &args.buffer, *offset, *count, multi_draw);
+                }
+            }
+            DrawCommand::SetDrawTransform(index) => {
+                if let Some(arena) = &self.draw_transform_arena {
+                    arena.bind(*index, render_pass);
+                }
+            }
+            DrawCommand::SetPushConstants(index) => {
+                if let Some(arena) = &self.push_constant_fallback {
+                    // Block 0 is the zeroed block draws start with
+                    arena.bind(*index + 1, render_pass);
+                } else if let Some((stages, offset, data)) = self.push_constant_data.get(*index) {
+                    render_pass.set_push_constants(*stages, *offset, data);
+                }
+            }
+            DrawCommand::SetUniformBlock(index) => {
+                if let Some(arena) = &self.uniform_arena {
+                    arena.bind(*index, render_pass);
+                }
+            }
+            DrawCommand::BindTexture(group, texture_id) => {
+                if let Some(bind_group) = self.texture_bind_groups.get(&(*group, texture_id.clone())) {
+                    render_pass.set_bind_group(*group, bind_group, &[]);
+                }
+            }
+        }
+    }
+}
+
+// Viewports need a positive size inside the target's top left corner, and a depth
+// range within 0..1 that doesn't run backwards
+fn check_viewport([x, y, width, height]: [f32; 4], min_depth: f32, max_depth: f32) -> Option<BuildError> {
+    if !(x >= 0.0 && y >= 0.0 && width > 0.0 && height > 0.0) {
+        return Some(BuildError::Viewport(format!("viewport rect {:?} needs a positive size and origin", [x, y, width, height])));
+    }
+
+    if !((0.0..=1.0).contains(&min_depth) && (0.0..=1.0).contains(&max_depth) && min_depth <= max_depth) {
+        return Some(BuildError::Viewport(format!("depth range {}..{} must be within 0..1 with min <= max", min_depth, max_depth)));
+    }
+
+    None
+}
+
+// Indirect args are read at `offset`, which must be 4-byte aligned and leave `size`
+// bytes in the buffer - wgpu would otherwise fail validation at draw time
+fn check_indirect_args(buffer: &str, args: &RawStorageBuffer, offset: u64, size: u64) -> Option<BuildError> {
+    if !offset.is_multiple_of(4) {
+        return Some(BuildError::IndirectArgs(format!("offset {} into {} is not a multiple of 4", offset, buffer)));
+    }
+
+    if offset + size > args.buffer.size() {
+        return Some(BuildError::IndirectArgs(format!(
+            "{} is {} bytes, too small for {} bytes of args at offset {}", buffer, args.buffer.size(), size, offset
+        )));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(name: &str) -> ResourceHandle {
+        ResourceHandle::new(name.to_string(), ResourceType::Mesh)
+    }
+
+    fn texture(name: &str) -> ResourceHandle {
+        ResourceHandle::new(name.to_string(), ResourceType::Texture)
+    }
+
+    #[test]
+    fn draws_keep_the_latest_state_of_each_kind() {
+        let commands = [
+            DrawCommand::BindTexture(0, texture("a")),
+            DrawCommand::SetUniformBlock(0),
+            DrawCommand::DrawMesh(mesh("first")),
+            DrawCommand::BindTexture(0, texture("b")),
+            DrawCommand::BindTexture(1, texture("c")),
+            DrawCommand::DrawMesh(mesh("second")),
+        ];
+
+        let items = RenderNode::collect_draw_items(&commands, &[]);
+
+        assert_eq!(items.len(), 2);
+        assert_eq!((items[0].state.as_slice(), items[0].draw), (&[0, 1][..], 2));
+        // The second texture replaced the first in group 0, group 1 and the uniform block carry over
+        assert_eq!((items[1].state.as_slice(), items[1].draw), (&[1, 3, 4][..], 5));
+    }
+
+    #[test]
+    fn push_constants_are_kept_per_range() {
+        let push_constant_data = [
+            (wgpu::ShaderStages::VERTEX, 0, vec![0; 4]),
+            (wgpu::ShaderStages::VERTEX, 16, vec![0; 4]),
+            (wgpu::ShaderStages::VERTEX, 0, vec![1; 4]),
+        ];
+        let commands = [
+            DrawCommand::SetPushConstants(0),
+            DrawCommand::SetPushConstants(1),
+            DrawCommand::SetPushConstants(2),
+            DrawCommand::Draw(0..3, 0..1),
+        ];
+
+        let items = RenderNode::collect_draw_items(&commands, &push_constant_data);
+
+        // The write at offset 0 was replaced, the one at offset 16 wasn't
+        assert_eq!(items[0].state, vec![1, 2]);
+    }
+
+    #[test]
+    fn only_mesh_draws_take_the_draw_transform() {
+        let commands = [
+            DrawCommand::SetDrawTransform(7),
+            DrawCommand::DrawMesh(mesh("cube")),
+            DrawCommand::Draw(0..3, 0..1),
+            DrawCommand::DrawMeshInstanced(mesh("cube"), 0),
+        ];
+
+        let items = RenderNode::collect_draw_items(&commands, &[]);
+
+        assert_eq!(items.iter().map(|item| item.transform).collect::<Vec<_>>(), vec![Some(7), None, None]);
+        assert!(items.iter().all(|item| item.state == vec![0]));
+    }
+
+    #[test]
+    fn viewport_scissor_and_stencil_reference_are_replayed() {
+        let commands = [
+            DrawCommand::SetViewport([0.0, 0.0, 64.0, 64.0], 0.0, 1.0),
+            DrawCommand::SetScissor([0, 0, 32, 32]),
+            DrawCommand::SetStencilReference(1),
+            DrawCommand::Draw(0..3, 0..1),
+            DrawCommand::SetStencilReference(2),
+            DrawCommand::Draw(0..3, 0..1),
+        ];
+
+        let items = RenderNode::collect_draw_items(&commands, &[]);
+
+        assert_eq!(items[0].state, vec![0, 1, 2]);
+        assert_eq!(items[1].state, vec![0, 1, 4]);
+    }
+
+    #[test]
+    fn sorted_draws_reset_state_they_were_recorded_without() {
+        let commands = [
+            DrawCommand::Draw(0..3, 0..1),
+            DrawCommand::SetScissor([0, 0, 32, 32]),
+            DrawCommand::SetUniformBlock(1),
+            DrawCommand::Draw(0..3, 0..1),
+        ];
+
+        let items = RenderNode::collect_draw_items(&commands, &[]);
+
+        // Replayed after the second draw, the first must not keep its scissor or uniform block
+        assert!(items[0].state.is_empty());
+        assert_eq!(items[0].defaults, vec![
+            DefaultState::Viewport, DefaultState::Scissor, DefaultState::StencilReference,
+            DefaultState::UniformBlock, DefaultState::PushConstants,
+        ]);
+
+        assert_eq!(items[1].state, vec![1, 2]);
+        assert_eq!(items[1].defaults, vec![DefaultState::Viewport, DefaultState::StencilReference, DefaultState::PushConstants]);
+    }
 }
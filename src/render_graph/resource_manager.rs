@@ -1,9 +1,14 @@
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use crate::{Handle, MutHandle};
 
-use crate::types::{Instance, InstanceBuffer, Material, Mesh, Texture};
+use crate::render_graph::BuildError;
+use crate::render_graph::oit::{CompositeTarget, OitCompositor, OitTargets};
+use crate::render_graph::texture_pool::{PooledTexture, TexturePool, TransientMemoryReport, TransientTextureDescriptor, TransientUsage};
+use crate::shader::{CompiledShader, ShaderCache, ShaderPermutation, ShaderPreprocessor, WgslStruct};
+use crate::types::{IndirectArgs, Instance, InstanceBuffer, Material, Mesh, RawStorageBuffer, RawUniformBuffer, RenderTarget, StorageBuffer, Texture,
+                   TransformHandle, Uniform, UniformBuffer};
 
 type ResourceID = String;
 
@@ -11,7 +16,9 @@ type ResourceID = String;
 pub enum ResourceType{
     Mesh,
     Texture,
-    Material
+    Material,
+    Shader,
+    StorageBuffer,
 }
 
 #[derive(Clone, Eq, PartialEq, Hash)]
@@ -44,8 +51,41 @@ pub struct ResourceManager{
 
     materials: HashMap<ResourceHandle, Material>,
 
-    // Renderer Resources
-    depth_texture: Option<MutHandle<Texture>>,
+    // Compiled shader variants, keyed by ShaderPermutation::key() and their source
+    shaders: ShaderCache<Handle<CompiledShader>>,
+    // Storage buffers created by the user, bound by name with Command::BindStorageBuffer
+    storage_buffers: HashMap<ResourceHandle, Handle<RawStorageBuffer>>,
+    // Uniform buffers and transforms graph descriptions refer to by name
+    named_uniform_buffers: HashMap<String, Handle<RawUniformBuffer>>,
+    named_transforms: HashMap<String, TransformHandle>,
+
+    // Every shader is preprocessed from this, so sources added to it are includable everywhere
+    shader_preprocessor: ShaderPreprocessor,
+
+    // Renderer Resources, one of each per MSAA sample count in use
+    depth_textures: HashMap<u32, MutHandle<Texture>>,
+    msaa_targets: HashMap<u32, MutHandle<RenderTarget>>,
+    oit_targets: HashMap<u32, MutHandle<OitTargets>>,
+    oit_compositor: Option<OitCompositor>,
+
+    // Flags that RunPolicy::WhenFlag nodes wait on
+    render_flags: HashSet<String>,
+
+    // Offscreen textures declared by name, and the pool that backs them each frame
+    transient_descriptors: HashMap<String, TransientTextureDescriptor>,
+    texture_pool: TexturePool,
+
+    // The sample count nodes render at unless they set their own
+    sample_count: u32,
+    // What the OIT targets' formats support, checked by OIT nodes on top of the depth format's counts
+    oit_sample_counts: Vec<u32>,
+
+    depth_format: wgpu::TextureFormat,
+    // The depth formats the device supports, with the sample counts each can render at
+    depth_formats: HashMap<wgpu::TextureFormat, Vec<u32>>,
+
+    // Where nodes sort their draws from, once a camera has been given
+    view_position: Option<nalgebra::Vector3<f32>>,
 
     device: Handle<wgpu::Device>,
     queue: Handle<wgpu::Queue>,
@@ -55,13 +95,35 @@ pub struct ResourceManager{
 
 impl ResourceManager{
     pub fn new(device: Handle<wgpu::Device>, queue: Handle<wgpu::Queue>,
-               surface_configuration: MutHandle<wgpu::SurfaceConfiguration>) -> Self {
+               surface_configuration: MutHandle<wgpu::SurfaceConfiguration>,
+               depth_formats: HashMap<wgpu::TextureFormat, Vec<u32>>, oit_sample_counts: Vec<u32>) -> Self {
         Self{
             meshes: HashMap::new(),
             textures: HashMap::new(),
             materials: HashMap::new(),
+            shaders: ShaderCache::new(),
+            storage_buffers: HashMap::new(),
+            named_uniform_buffers: HashMap::new(),
+            named_transforms: HashMap::new(),
+            shader_preprocessor: ShaderPreprocessor::new(),
 
-            depth_texture: None,
+            depth_textures: HashMap::new(),
+            msaa_targets: HashMap::new(),
+            oit_targets: HashMap::new(),
+            oit_compositor: None,
+
+            render_flags: HashSet::new(),
+
+            transient_descriptors: HashMap::new(),
+            texture_pool: TexturePool::new(device.clone()),
+
+            sample_count: 1,
+            oit_sample_counts,
+
+            depth_format: wgpu::TextureFormat::Depth32Float,
+            depth_formats,
+
+            view_position: None,
 
             surface_configuration,
 
@@ -98,40 +160,242 @@ impl ResourceManager{
         self.textures.get(&id)
     }
 
-    pub fn load_depth_texture(&mut self) -> MutHandle<Texture>{
-        if self.depth_texture.is_some(){
+    // Make a Rust type's WGSL declaration includable from shaders as `<WGSL_NAME>.wgsl`.
+    // Nodes already built only see a changed declaration once they're rebuilt.
+    pub fn add_shader_struct<T: WgslStruct>(&mut self){
+        self.shader_preprocessor.add_struct::<T>();
+    }
+
+    // The source is preprocessed every time, so a variant is only reused while its
+    // files are unchanged and edits are picked up when a node is rebuilt
+    pub fn load_shader(&mut self, permutation: &ShaderPermutation) -> Result<Handle<CompiledShader>, BuildError>{
+        let id = ResourceHandle::new(permutation.key(), ResourceType::Shader);
 
-            let is_width ;
-            let is_height ;
+        // Preprocess the source with this variant's defines
+        let source = permutation.preprocessor(&self.shader_preprocessor).process_file_with_map(permutation.path())
+            .map_err(|e| BuildError::Preprocess(e.to_string()))?;
 
-            // Define a closure to check if the depth texture has the same size as the surface configuration
-            {
-                let surface_config = self.surface_configuration.clone();
-                let surface_config = surface_config.lock().unwrap();
+        let device = &self.device;
+        self.shaders.get_or_compile(id.get_id(), &source.source.clone(), || {
+            CompiledShader::new(device, id.get_id(), source).map(Handle::new).map_err(BuildError::Shader)
+        })
+    }
 
-                let texture_size = self.depth_texture
-                    .as_ref().unwrap()
-                    .lock().unwrap()
-                    .get_texture_size();
+    pub fn get_sample_count(&self) -> u32{
+        self.sample_count
+    }
 
-                is_width = texture_size.width == surface_config.width;
-                is_height = texture_size.height == surface_config.height;
-            }
+    // The sample counts the current depth format can render at
+    pub fn get_supported_sample_counts(&self) -> &[u32]{
+        self.depth_formats.get(&self.depth_format).map_or(&[1], |counts| counts.as_slice())
+    }
+
+    pub fn get_oit_sample_counts(&self) -> &[u32]{
+        &self.oit_sample_counts
+    }
+
+    pub fn is_sample_count_supported(&self, count: u32) -> bool{
+        self.get_supported_sample_counts().contains(&count)
+    }
+
+    // Set the MSAA sample count for every node that doesn't set its own. Nodes
+    // rebuild their pipelines before the next frame.
+    pub fn set_sample_count(&mut self, count: u32) -> Result<(), BuildError>{
+        if !self.is_sample_count_supported(count){
+            return Err(BuildError::UnsupportedSampleCount{ count, supported: self.get_supported_sample_counts().to_vec() });
+        }
+
+        self.sample_count = count;
+        Ok(())
+    }
 
+    pub fn get_depth_format(&self) -> wgpu::TextureFormat{
+        self.depth_format
+    }
+
+    // Switch every depth texture to `format`, which has to support the current sample
+    // count. Nodes rebuild their pipelines before the next frame.
+    pub fn set_depth_format(&mut self, format: wgpu::TextureFormat) -> Result<(), BuildError>{
+        let Some(supported) = self.depth_formats.get(&format) else {
+            return Err(BuildError::UnsupportedDepthFormat(format));
+        };
+        if !supported.contains(&self.sample_count){
+            return Err(BuildError::UnsupportedSampleCount{ count: self.sample_count, supported: supported.clone() });
+        }
+
+        self.depth_format = format;
+        Ok(())
+    }
+
+    pub fn get_view_position(&self) -> Option<nalgebra::Vector3<f32>>{
+        self.view_position
+    }
+
+    pub fn set_view_position(&mut self, position: nalgebra::Vector3<f32>){
+        self.view_position = Some(position);
+    }
+
+    // The format nodes render color to
+    pub fn get_color_format(&self) -> wgpu::TextureFormat{
+        self.surface_configuration.lock().unwrap().format
+    }
+
+    fn surface_size(&self) -> (u32, u32){
+        let surface_config = self.surface_configuration.lock().unwrap();
+        (surface_config.width, surface_config.height)
+    }
+
+    // The depth texture for nodes rendering at `sample_count`, resized to the surface if needed
+    pub fn load_depth_texture(&mut self, sample_count: u32) -> MutHandle<Texture>{
+        let surface_size = self.surface_size();
+
+        // Recreated from scratch if the depth format changed
+        let depth_format = self.depth_format;
+        self.depth_textures.retain(|_, texture| texture.lock().unwrap().texture.format() == depth_format);
+
+        if let Some(depth_texture) = self.depth_textures.get(&sample_count){
             // Check if the depth texture has the same size as the surface configuration
-            if !is_width || !is_height {
-                self.depth_texture.as_ref().unwrap()
-                    .lock().unwrap()
-                    .resize_screen_texture(&self.device, self.surface_configuration.clone());
+            let mut texture = depth_texture.lock().unwrap();
+            let texture_size = texture.get_texture_size();
+
+            if (texture_size.width, texture_size.height) != surface_size {
+                texture.resize_screen_texture(&self.device, self.surface_configuration.clone());
+            }
+
+            return depth_texture.clone();
+        }
+
+        let texture = Texture::create_depth_texture(&self.device, self.surface_configuration.clone(), depth_format, sample_count);
+        let texture = MutHandle::new(texture);
+        self.depth_textures.insert(sample_count, texture.clone());
+
+        texture
+    }
+
+    // The multisampled color target for nodes rendering at `sample_count`. Nodes
+    // draw into it and resolve into their real target.
+    pub fn load_msaa_target(&mut self, sample_count: u32) -> MutHandle<RenderTarget>{
+        let surface_size = self.surface_size();
+
+        if let Some(target) = self.msaa_targets.get(&sample_count){
+            let mut render_target = target.lock().unwrap();
+            let target_size = render_target.get_texture_size();
+
+            if (target_size.width, target_size.height) != surface_size {
+                render_target.resize(&self.device, self.surface_configuration.clone());
             }
 
-            return self.depth_texture.as_ref().unwrap().clone();
+            return target.clone();
         }
 
-        let texture = Texture::create_depth_texture(&self.device, self.surface_configuration.clone());
-        self.depth_texture = Some(MutHandle::new(texture));
+        let target = MutHandle::new(RenderTarget::new(&self.device, self.surface_configuration.clone(), sample_count));
+        self.msaa_targets.insert(sample_count, target.clone());
 
-        self.depth_texture.as_ref().unwrap().clone()
+        target
+    }
+
+    // The accumulation and revealage targets for OIT nodes rendering at `sample_count`
+    pub fn load_oit_targets(&mut self, sample_count: u32) -> MutHandle<OitTargets>{
+        let surface_size = self.surface_size();
+
+        if let Some(targets) = self.oit_targets.get(&sample_count){
+            if targets.lock().unwrap().get_size() == surface_size {
+                return targets.clone();
+            }
+        }
+
+        // New, or the surface was resized - the composite bind group goes with the old views
+        let compositor = self.oit_compositor.get_or_insert_with(|| OitCompositor::new(&self.device));
+        let targets = MutHandle::new(OitTargets::new(&self.device, self.surface_configuration.clone(), sample_count, compositor));
+        self.oit_targets.insert(sample_count, targets.clone());
+
+        targets
+    }
+
+    // Declare a transient texture nodes can render into and sample by name. Nodes
+    // using it need rebuilding if it's declared again with another format.
+    pub fn declare_transient_texture(&mut self, name: &str, descriptor: TransientTextureDescriptor){
+        self.transient_descriptors.insert(name.to_string(), descriptor);
+    }
+
+    pub fn get_transient_descriptor(&self, name: &str) -> Option<TransientTextureDescriptor>{
+        self.transient_descriptors.get(name).copied()
+    }
+
+    // The pool's name for the depth texture that goes with a transient target
+    pub(crate) fn transient_depth_name(name: &str) -> String{
+        format!("{}#depth", name)
+    }
+
+    pub fn set_render_flag(&mut self, flag: &str, set: bool){
+        if set {
+            self.render_flags.insert(flag.to_string());
+        } else {
+            self.render_flags.remove(flag);
+        }
+    }
+
+    pub fn is_render_flag_set(&self, flag: &str) -> bool{
+        self.render_flags.contains(flag)
+    }
+
+    // Back this frame's transient textures from the pool, given the first and last
+    // graph positions each is used at. Depth textures for nodes rendering to a
+    // transient with depth are sized like their color texture. Persistent ones keep
+    // a texture of their own from frame to frame.
+    pub(crate) fn allocate_transients(&mut self, lifetimes: HashMap<String, (usize, usize)>,
+                                      depth_lifetimes: HashMap<String, (usize, usize)>, persistent: &HashSet<String>){
+        let (width, height) = self.surface_size();
+        let mut usages = Vec::new();
+
+        for (name, (first, last)) in lifetimes{
+            if let Some(descriptor) = self.transient_descriptors.get(&name){
+                let size = descriptor.extent(width, height);
+                let persistent = persistent.contains(&name);
+                usages.push(TransientUsage{ name, format: descriptor.format, size, first, last, persistent });
+            }
+        }
+
+        for (name, (first, last)) in depth_lifetimes{
+            if let Some(descriptor) = self.transient_descriptors.get(&name){
+                let size = descriptor.extent(width, height);
+                usages.push(TransientUsage{ name: Self::transient_depth_name(&name), format: self.depth_format, size, first, last, persistent: false });
+            }
+        }
+
+        self.texture_pool.allocate(usages);
+    }
+
+    pub(crate) fn get_transient(&self, name: &str) -> Option<&PooledTexture>{
+        self.texture_pool.get(name)
+    }
+
+    pub fn get_transient_memory_report(&self) -> TransientMemoryReport{
+        self.texture_pool.get_report()
+    }
+
+    // Blend what OIT nodes at `sample_count` drew over the frame, clearing it first if
+    // no other node has drawn to it yet
+    pub fn composite_oit(&mut self, sample_count: u32, clear: bool, texture_view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder){
+        let targets = self.load_oit_targets(sample_count);
+        let targets = targets.lock().unwrap();
+
+        let msaa_target = (sample_count > 1).then(|| self.load_msaa_target(sample_count));
+        let msaa_target = msaa_target.as_ref().map(|target| target.lock().unwrap());
+        let (color_view, resolve_target) = match &msaa_target {
+            Some(msaa_target) => (&msaa_target.view, Some(texture_view)),
+            None => (texture_view, None),
+        };
+
+        let target = CompositeTarget{
+            view: color_view,
+            resolve_target,
+            format: self.get_color_format(),
+            sample_count,
+            clear,
+        };
+        let compositor = self.oit_compositor.get_or_insert_with(|| OitCompositor::new(&self.device));
+        compositor.composite(&self.device, &targets, target, encoder);
     }
 
     pub fn load_material(&mut self, id: ResourceHandle, material: Material) -> Option<&Material>{
@@ -145,6 +409,47 @@ impl ResourceManager{
         self.materials.get(&id)
     }
 
+    // Create a storage buffer that nodes can bind as `name`. Creating another buffer
+    // with the same name replaces it for nodes built afterwards.
+    pub fn create_storage_buffer<T: bytemuck::Pod>(&mut self, name: &str, data: &[T], read_only: bool) -> StorageBuffer<T>{
+        let storage_buffer = StorageBuffer::new(&self.device, self.queue.clone(), data, read_only);
+
+        let id = ResourceHandle::new(name.to_string(), ResourceType::StorageBuffer);
+        self.storage_buffers.insert(id, storage_buffer.get_raw());
+
+        storage_buffer
+    }
+
+    // Like create_storage_buffer, for a buffer of indirect draw arguments
+    pub fn create_indirect_buffer<T: IndirectArgs>(&mut self, name: &str, args: &[T], read_only: bool) -> StorageBuffer<T>{
+        let storage_buffer = StorageBuffer::new_indirect(&self.device, self.queue.clone(), args, read_only);
+
+        let id = ResourceHandle::new(name.to_string(), ResourceType::StorageBuffer);
+        self.storage_buffers.insert(id, storage_buffer.get_raw());
+
+        storage_buffer
+    }
+
+    pub fn get_storage_buffer(&self, id: ResourceHandle) -> Option<Handle<RawStorageBuffer>>{
+        self.storage_buffers.get(&id).cloned()
+    }
+
+    pub fn register_uniform_buffer<T: Uniform>(&mut self, name: &str, buffer: &UniformBuffer<T>){
+        self.named_uniform_buffers.insert(name.to_string(), buffer.get_raw());
+    }
+
+    pub fn get_named_uniform_buffer(&self, name: &str) -> Option<Handle<RawUniformBuffer>>{
+        self.named_uniform_buffers.get(name).cloned()
+    }
+
+    pub fn register_transform(&mut self, name: &str, transform: TransformHandle){
+        self.named_transforms.insert(name.to_string(), transform);
+    }
+
+    pub fn get_named_transform(&self, name: &str) -> Option<TransformHandle>{
+        self.named_transforms.get(name).cloned()
+    }
+
     pub fn build_instance_buffer(&self, instances: &[Instance]) -> InstanceBuffer{
         InstanceBuffer::new(&self.device, instances.to_vec())
     }
@@ -153,10 +458,6 @@ impl ResourceManager{
         self.meshes.get(&id)
     }
 
-    pub fn get_mesh_mut(&mut self, id: ResourceHandle) -> Option<&mut Mesh>{
-        self.meshes.get_mut(&id)
-    }
-
     pub fn get_texture(&self, id: ResourceHandle) -> Option<&Texture>{
         self.textures.get(&id)
     }
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+/// When a render node runs. A node that skips a frame keeps what it last rendered
+/// into its transient target (see RenderNode::render_to_transient), so later nodes
+/// can keep sampling it. Skipped nodes that render to the frame just draw nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunPolicy{
+    #[default]
+    Always,
+    // The first frame after the node is built
+    Once,
+    EveryNFrames(u32),
+    // The frame after Renderer::request_node_run
+    OnDemand,
+    // Every frame the named flag is set with Renderer::set_render_flag
+    WhenFlag(String),
+}
+
+// Where a node is in its run policy's schedule
+pub(crate) struct RunState{
+    // Frames since the node last ran, None until it has run since it was built
+    frames_since_run: Option<u32>,
+    run_requested: bool,
+    // The pooled texture it last rendered into, so it runs again if that's recreated
+    last_target_id: Option<u64>,
+    runs_this_frame: bool,
+}
+
+impl RunState{
+    pub fn new() -> Self{
+        Self{
+            frames_since_run: None,
+            run_requested: false,
+            last_target_id: None,
+            runs_this_frame: true,
+        }
+    }
+
+    pub fn request_run(&mut self){
+        self.run_requested = true;
+    }
+
+    // Run next frame whatever the policy, as after a rebuild
+    pub fn restart(&mut self){
+        self.frames_since_run = None;
+    }
+
+    pub fn runs_this_frame(&self) -> bool{
+        self.runs_this_frame
+    }
+
+    // Decide whether the node runs this frame, given the texture it renders into (if
+    // it has a transient target) and which render flags are set
+    pub fn update(&mut self, policy: &RunPolicy, target_id: Option<u64>, is_flag_set: impl Fn(&str) -> bool) -> bool{
+        let due = match policy {
+            RunPolicy::Always => true,
+            RunPolicy::Once => self.frames_since_run.is_none(),
+            RunPolicy::EveryNFrames(frames) => self.frames_since_run.is_none_or(|since| since + 1 >= (*frames).max(1)),
+            RunPolicy::OnDemand => self.run_requested,
+            RunPolicy::WhenFlag(flag) => is_flag_set(flag),
+        };
+
+        // A new target texture (after a resize, say) has nothing in it yet
+        self.runs_this_frame = due || target_id != self.last_target_id;
+
+        if self.runs_this_frame{
+            self.frames_since_run = Some(0);
+            self.run_requested = false;
+            self.last_target_id = target_id;
+        }else if let Some(frames) = &mut self.frames_since_run{
+            *frames += 1;
+        }
+
+        self.runs_this_frame
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // Which of the next `frames` frames the node runs in, with no flags set and the same target
+    fn schedule(state: &mut RunState, policy: &RunPolicy, frames: usize) -> Vec<bool>{
+        (0..frames).map(|_| state.update(policy, None, |_| false)).collect()
+    }
+
+    #[test]
+    fn always_runs_every_frame(){
+        assert_eq!(schedule(&mut RunState::new(), &RunPolicy::Always, 3), vec![true; 3]);
+    }
+
+    #[test]
+    fn once_runs_again_after_a_restart(){
+        let mut state = RunState::new();
+        assert_eq!(schedule(&mut state, &RunPolicy::Once, 3), vec![true, false, false]);
+
+        state.restart();
+        assert_eq!(schedule(&mut state, &RunPolicy::Once, 2), vec![true, false]);
+    }
+
+    #[test]
+    fn every_n_frames_counts_from_the_last_run(){
+        let mut state = RunState::new();
+        assert_eq!(schedule(&mut state, &RunPolicy::EveryNFrames(3), 7), vec![true, false, false, true, false, false, true]);
+
+        // 0 and 1 both mean every frame
+        assert_eq!(schedule(&mut RunState::new(), &RunPolicy::EveryNFrames(0), 3), vec![true; 3]);
+        assert_eq!(schedule(&mut RunState::new(), &RunPolicy::EveryNFrames(1), 3), vec![true; 3]);
+    }
+
+    #[test]
+    fn on_demand_runs_once_per_request(){
+        let mut state = RunState::new();
+        assert_eq!(schedule(&mut state, &RunPolicy::OnDemand, 2), vec![false, false]);
+
+        state.request_run();
+        state.request_run();
+        assert_eq!(schedule(&mut state, &RunPolicy::OnDemand, 2), vec![true, false]);
+    }
+
+    #[test]
+    fn when_flag_follows_the_flag(){
+        let mut state = RunState::new();
+        let policy = RunPolicy::WhenFlag("shadows".to_string());
+
+        assert!(state.update(&policy, None, |flag| flag == "shadows"));
+        assert!(!state.update(&policy, None, |flag| flag == "reflections"));
+        assert!(!state.update(&policy, None, |_| false));
+    }
+
+    #[test]
+    fn a_new_target_texture_forces_a_run(){
+        let mut state = RunState::new();
+        let policy = RunPolicy::OnDemand;
+
+        assert!(state.update(&policy, Some(1), |_| false));
+        assert!(!state.update(&policy, Some(1), |_| false));
+        // The pool handed out a different texture, which has nothing in it yet
+        assert!(state.update(&policy, Some(2), |_| false));
+        assert!(!state.update(&policy, Some(2), |_| false));
+        assert!(!state.runs_this_frame());
+    }
+}
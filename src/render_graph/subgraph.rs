@@ -0,0 +1,154 @@
+use std::cell::Cell;
+
+use crate::render_graph::{GraphEntry, RenderGraph, RenderNode, ResourceManager, TransientTextureDescriptor};
+use crate::types::{BindSlot, RawUniformBuffer, Uniform, UniformBuffer};
+use crate::{Handle, MutHandle};
+
+/// What changes between instances of a subgraph. Every render node directly in the
+/// subgraph gets them - nested subgraphs keep their own.
+#[derive(Clone, Default)]
+pub struct SubgraphParameters{
+    camera: Option<(Handle<RawUniformBuffer>, BindSlot)>,
+    target: Option<String>,
+    viewport: Option<[f32; 4]>,
+}
+
+impl SubgraphParameters{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    // Bind this camera buffer in every node, at `slot`
+    pub fn camera<T: Uniform>(mut self, buffer: &UniformBuffer<T>, slot: BindSlot) -> Self{
+        self.camera = Some((buffer.get_raw(), slot));
+        self
+    }
+
+    // Render into this transient texture wherever the nodes would render to the frame
+    pub fn target(mut self, name: &str) -> Self{
+        self.target = Some(name.to_string());
+        self
+    }
+
+    // Only draw into part of the target, in pixels from the top left. Clears aren't
+    // limited to the viewport, so the nodes shouldn't set clear values when other
+    // instances draw into the same target (see RenderNode::set_clear_color).
+    pub fn viewport(mut self, x: f32, y: f32, width: f32, height: f32) -> Self{
+        self.viewport = Some([x, y, width, height]);
+        self
+    }
+}
+
+// A render graph embedded in another as a single node
+pub(crate) struct Subgraph{
+    pub name: String,
+    pub graph: RenderGraph,
+    parameters: SubgraphParameters,
+}
+
+impl Subgraph{
+    pub fn new(name: String, graph: RenderGraph, parameters: SubgraphParameters) -> Self{
+        let mut subgraph = Self{ name, graph, parameters: SubgraphParameters::default() };
+        subgraph.set_parameters(parameters);
+        subgraph
+    }
+
+    // Swap the parameters on every node, undoing the old ones. The nodes need
+    // rebuilding afterwards.
+    pub fn set_parameters(&mut self, parameters: SubgraphParameters){
+        let old = std::mem::replace(&mut self.parameters, parameters);
+
+        for node in self.graph.direct_nodes_mut(){
+            if let Some((_, slot)) = &old.camera{
+                node.remove_uniform_buffer(*slot);
+            }
+            if let Some((buffer, slot)) = &self.parameters.camera{
+                node.add_raw_uniform_buffer(buffer.clone(), *slot);
+            }
+
+            // Nodes with a transient target of their own (a shadow map, say) keep it
+            if node.get_transient_target() == old.target.as_deref(){
+                node.set_transient_target(self.parameters.target.clone());
+            }
+
+            if node.get_viewport() == old.viewport{
+                node.set_viewport(self.parameters.viewport);
+            }
+        }
+    }
+}
+
+/// A chain of passes that can be instantiated many times, such as once per camera
+/// or viewport. The closure adds one instance's nodes to a SubgraphBuilder.
+pub struct RenderGraphTemplate{
+    name: String,
+    build: Box<dyn Fn(&mut SubgraphBuilder)>,
+    instances: Cell<usize>,
+}
+
+impl RenderGraphTemplate{
+    pub fn new<F: Fn(&mut SubgraphBuilder) + 'static>(name: &str, build: F) -> Self{
+        Self{
+            name: name.to_string(),
+            build: Box::new(build),
+            instances: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn instantiate(&self, device: Handle<wgpu::Device>, queue: Handle<wgpu::Queue>,
+                              resource_manager: MutHandle<ResourceManager>, parameters: SubgraphParameters) -> Subgraph{
+        let instance = self.instances.get();
+        self.instances.set(instance + 1);
+
+        let mut builder = SubgraphBuilder{
+            instance,
+            graph: RenderGraph::new(),
+            device,
+            queue,
+            resource_manager,
+        };
+        (self.build)(&mut builder);
+
+        Subgraph::new(format!("{} #{}", self.name, instance), builder.graph, parameters)
+    }
+}
+
+/// Collects the nodes of one instance of a RenderGraphTemplate.
+pub struct SubgraphBuilder{
+    instance: usize,
+    graph: RenderGraph,
+    device: Handle<wgpu::Device>,
+    queue: Handle<wgpu::Queue>,
+    resource_manager: MutHandle<ResourceManager>,
+}
+
+impl SubgraphBuilder{
+    // Which instance of the template this is, counting from 0
+    pub fn get_instance(&self) -> usize{
+        self.instance
+    }
+
+    pub fn get_render_node(&self, name: String) -> RenderNode{
+        RenderNode::new(name, self.device.clone(), self.queue.clone())
+    }
+
+    // The instance is built along with the graph it's added to. Its nodes are only
+    // reached through the instance's own ID, so no ID is handed out for them.
+    pub fn add_render_node(&mut self, node: RenderNode){
+        self.graph.insert_entry(GraphEntry::Node(Box::new(node)));
+    }
+
+    // Declare a transient texture for this instance alone and return its name, so
+    // instances don't clear each other's intermediate results
+    pub fn declare_transient_texture(&mut self, name: &str, descriptor: TransientTextureDescriptor) -> String{
+        let name = format!("{}#{}", name, self.instance);
+        self.resource_manager.lock().unwrap().declare_transient_texture(&name, descriptor);
+        name
+    }
+
+    // Nest an instance of another template
+    pub fn add_template(&mut self, template: &RenderGraphTemplate, parameters: SubgraphParameters){
+        let subgraph = template.instantiate(self.device.clone(), self.queue.clone(), self.resource_manager.clone(), parameters);
+        self.graph.insert_entry(GraphEntry::Subgraph(subgraph));
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use log::info;
+
+use crate::Handle;
+
+// How big a transient texture is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransientSize{
+    // A fraction of the surface size, following it on resize
+    Surface(f32),
+    Fixed(u32, u32),
+}
+
+/// An offscreen texture that only lives for part of the frame. Nodes render into
+/// it with `RenderNode::render_to_transient` and later nodes sample it with
+/// `Command::BindTransient`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransientTextureDescriptor{
+    pub format: wgpu::TextureFormat,
+    pub size: TransientSize,
+}
+
+impl TransientTextureDescriptor{
+    // Surface sized
+    pub fn new(format: wgpu::TextureFormat) -> Self{
+        Self{ format, size: TransientSize::Surface(1.0) }
+    }
+
+    pub fn scaled(format: wgpu::TextureFormat, scale: f32) -> Self{
+        Self{ format, size: TransientSize::Surface(scale) }
+    }
+
+    pub fn fixed(format: wgpu::TextureFormat, width: u32, height: u32) -> Self{
+        Self{ format, size: TransientSize::Fixed(width, height) }
+    }
+
+    pub(crate) fn extent(&self, surface_width: u32, surface_height: u32) -> wgpu::Extent3d{
+        let (width, height) = match self.size {
+            TransientSize::Surface(scale) => ((surface_width as f32 * scale) as u32, (surface_height as f32 * scale) as u32),
+            TransientSize::Fixed(width, height) => (width, height),
+        };
+
+        wgpu::Extent3d{ width: width.max(1), height: height.max(1), depth_or_array_layers: 1 }
+    }
+}
+
+// A transient texture's lifetime this frame: the first and last graph positions that use it
+pub(crate) struct TransientUsage{
+    pub name: String,
+    pub format: wgpu::TextureFormat,
+    pub size: wgpu::Extent3d,
+    pub first: usize,
+    pub last: usize,
+    // Written by a node that doesn't run every frame, so the texture must keep its
+    // contents between frames and is never shared
+    pub persistent: bool,
+}
+
+// What allocation needs to know about a pooled texture, kept apart from the GPU texture
+#[derive(Debug, Clone, PartialEq)]
+struct PoolSlot{
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    // The persistent transient this texture belongs to
+    owner: Option<String>,
+    // The last graph position using the texture in this frame's allocation
+    busy_until: Option<usize>,
+}
+
+impl PoolSlot{
+    fn bytes(&self) -> u64{
+        texture_bytes(self.format, self.size)
+    }
+}
+
+pub(crate) struct PooledTexture{
+    // Changes whenever a new texture is made, so nodes know to rebuild their bind groups
+    id: u64,
+    _texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    slot: PoolSlot,
+}
+
+impl PooledTexture{
+    pub fn get_id(&self) -> u64{
+        self.id
+    }
+
+    pub fn get_size(&self) -> wgpu::Extent3d{
+        self.slot.size
+    }
+}
+
+/// What the transient textures cost after aliasing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransientMemoryReport{
+    // Transient textures the graph uses
+    pub textures: usize,
+    // Textures actually allocated for them
+    pub pooled_textures: usize,
+    // Bytes the pooled textures take
+    pub allocated_bytes: u64,
+    // The most bytes of pooled textures in use at any one point in the frame
+    pub peak_bytes: u64,
+    // Bytes it would take without aliasing
+    pub unaliased_bytes: u64,
+}
+
+impl fmt::Display for TransientMemoryReport{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        write!(f, "{} transient textures in {} pooled textures, {:.2} MiB allocated, peak {:.2} MiB in use ({:.2} MiB without aliasing)",
+               self.textures, self.pooled_textures,
+               mib(self.allocated_bytes), mib(self.peak_bytes), mib(self.unaliased_bytes))
+    }
+}
+
+// Reuses transient textures between uses whose lifetimes don't overlap
+pub(crate) struct TexturePool{
+    device: Handle<wgpu::Device>,
+    textures: Vec<PooledTexture>,
+    // Transient name -> pooled texture id, for this frame
+    assignments: HashMap<String, u64>,
+    next_id: u64,
+    report: TransientMemoryReport,
+}
+
+impl TexturePool{
+    pub fn new(device: Handle<wgpu::Device>) -> Self{
+        Self{
+            device,
+            textures: Vec::new(),
+            assignments: HashMap::new(),
+            next_id: 0,
+            report: TransientMemoryReport::default(),
+        }
+    }
+
+    // Hand out a texture to each usage (see assign_slots). Textures nothing uses any
+    // more (after a resize, say) are freed.
+    pub fn allocate(&mut self, mut usages: Vec<TransientUsage>){
+        let mut slots: Vec<PoolSlot> = self.textures.iter().map(|texture| texture.slot.clone()).collect();
+        let assigned = assign_slots(&mut slots, &mut usages);
+
+        // Make textures for the slots that are new this frame
+        for slot in slots[self.textures.len()..].iter(){
+            let texture = self.create_texture(slot.clone());
+            self.textures.push(texture);
+        }
+        for (texture, slot) in self.textures.iter_mut().zip(slots.iter()){
+            texture.slot.busy_until = slot.busy_until;
+        }
+
+        self.assignments = usages.iter().zip(assigned.iter())
+            .map(|(usage, index)| (usage.name.clone(), self.textures[*index].id))
+            .collect();
+        self.textures.retain(|texture| texture.slot.busy_until.is_some());
+
+        let report = memory_report(&slots, &usages, &assigned);
+        if report != self.report{
+            info!("{}", report);
+            self.report = report;
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PooledTexture>{
+        let id = self.assignments.get(name)?;
+        self.textures.iter().find(|texture| texture.id == *id)
+    }
+
+    pub fn get_report(&self) -> TransientMemoryReport{
+        self.report
+    }
+
+    fn create_texture(&mut self, slot: PoolSlot) -> PooledTexture{
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor{
+            label: Some("Transient Texture"),
+            size: slot.size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: slot.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.next_id += 1;
+        PooledTexture{ id: self.next_id, _texture: texture, view, slot }
+    }
+}
+
+// Give each usage a slot, returning the slot index for each in the (sorted) usages.
+// Usages with the same format and size share a slot when one ends before the other
+// starts; persistent ones keep a slot of their own. Slots are reused before new ones
+// are added at the end, and slots left with no usage end up with busy_until unset.
+fn assign_slots(slots: &mut Vec<PoolSlot>, usages: &mut [TransientUsage]) -> Vec<usize>{
+    // By name too, so the same graph gets the same textures every frame
+    usages.sort_by(|a, b| (a.first, a.last, &a.name).cmp(&(b.first, b.last, &b.name)));
+
+    for slot in slots.iter_mut(){
+        slot.busy_until = None;
+    }
+
+    usages.iter().map(|usage| {
+        let owner = usage.persistent.then(|| usage.name.clone());
+        let free = slots.iter().position(|slot| {
+            slot.format == usage.format && slot.size == usage.size && slot.owner == owner
+                && slot.busy_until.is_none_or(|until| until < usage.first)
+        });
+
+        let index = free.unwrap_or_else(|| {
+            slots.push(PoolSlot{ format: usage.format, size: usage.size, owner, busy_until: None });
+            slots.len() - 1
+        });
+
+        slots[index].busy_until = Some(usage.last);
+        index
+    }).collect()
+}
+
+// The memory the slots in use take, against giving every usage its own texture.
+// `assigned` is the slot of each usage, from assign_slots.
+fn memory_report(slots: &[PoolSlot], usages: &[TransientUsage], assigned: &[usize]) -> TransientMemoryReport{
+    let used = slots.iter().filter(|slot| slot.busy_until.is_some());
+
+    // A slot is in use from the first to the last position of each usage it holds.
+    // Persistent textures hold their contents across frames, so they always are.
+    let end = usages.iter().map(|usage| usage.last + 1).max().unwrap_or(0);
+    let peak_bytes = (0..end).map(|position| {
+        let mut live: Vec<usize> = usages.iter().zip(assigned.iter())
+            .filter(|(usage, _)| usage.persistent || (usage.first..=usage.last).contains(&position))
+            .map(|(_, slot)| *slot)
+            .collect();
+        live.sort_unstable();
+        live.dedup();
+
+        live.iter().map(|slot| slots[*slot].bytes()).sum()
+    }).max().unwrap_or(0);
+
+    TransientMemoryReport{
+        textures: usages.len(),
+        pooled_textures: used.clone().count(),
+        allocated_bytes: used.map(|slot| slot.bytes()).sum(),
+        peak_bytes,
+        unaliased_bytes: usages.iter().map(|usage| texture_bytes(usage.format, usage.size)).sum(),
+    }
+}
+
+fn texture_bytes(format: wgpu::TextureFormat, size: wgpu::Extent3d) -> u64{
+    // Combined depth-stencil formats have no copy size of their own
+    let block_size = format.block_copy_size(None).unwrap_or(match format {
+        wgpu::TextureFormat::Depth32FloatStencil8 => 8,
+        _ => 4,
+    });
+
+    block_size as u64 * size.width as u64 * size.height as u64
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const COLOR: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    const HDR: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    fn size(width: u32, height: u32) -> wgpu::Extent3d{
+        wgpu::Extent3d{ width, height, depth_or_array_layers: 1 }
+    }
+
+    fn usage(name: &str, format: wgpu::TextureFormat, first: usize, last: usize) -> TransientUsage{
+        TransientUsage{ name: name.to_string(), format, size: size(64, 64), first, last, persistent: false }
+    }
+
+    // The slot each usage got, by name
+    fn assign(slots: &mut Vec<PoolSlot>, mut usages: Vec<TransientUsage>) -> HashMap<String, usize>{
+        let assigned = assign_slots(slots, &mut usages);
+        usages.into_iter().map(|usage| usage.name).zip(assigned).collect()
+    }
+
+    #[test]
+    fn textures_that_dont_overlap_share_a_slot(){
+        let mut slots = Vec::new();
+        let assigned = assign(&mut slots, vec![
+            usage("shadow", COLOR, 0, 1),
+            usage("bloom", COLOR, 2, 3),
+            usage("blur", COLOR, 3, 4),
+        ]);
+
+        assert_eq!(assigned["shadow"], assigned["bloom"]);
+        // Bloom is still read at 3, where blur is written
+        assert_ne!(assigned["bloom"], assigned["blur"]);
+        assert_eq!(slots.len(), 2);
+    }
+
+    #[test]
+    fn only_matching_formats_and_sizes_alias(){
+        let mut slots = Vec::new();
+        let mut small = usage("small", COLOR, 2, 3);
+        small.size = size(32, 32);
+        let assigned = assign(&mut slots, vec![usage("color", COLOR, 0, 1), usage("hdr", HDR, 2, 3), small]);
+
+        assert_eq!(slots.len(), 3);
+        assert_ne!(assigned["color"], assigned["hdr"]);
+        assert_ne!(assigned["color"], assigned["small"]);
+    }
+
+    #[test]
+    fn persistent_textures_keep_their_own_slot(){
+        let mut slots = Vec::new();
+        let mut cached = usage("cached", COLOR, 0, 0);
+        cached.persistent = true;
+
+        let first = assign(&mut slots, vec![cached, usage("scratch", COLOR, 1, 1)]);
+        assert_ne!(first["cached"], first["scratch"]);
+
+        // Next frame the persistent texture comes back to the same slot, even used later on
+        let mut cached = usage("cached", COLOR, 3, 3);
+        cached.persistent = true;
+        let second = assign(&mut slots, vec![usage("scratch", COLOR, 0, 1), cached]);
+        assert_eq!(first, second);
+        assert_eq!(slots.len(), 2);
+    }
+
+    #[test]
+    fn unused_slots_are_left_free(){
+        let mut slots = Vec::new();
+        assign(&mut slots, vec![usage("a", COLOR, 0, 1), usage("b", HDR, 0, 1)]);
+        assign(&mut slots, vec![usage("a", COLOR, 0, 1)]);
+
+        assert_eq!(slots.iter().filter(|slot| slot.busy_until.is_some()).count(), 1);
+    }
+
+    #[test]
+    fn report_counts_allocated_peak_and_unaliased_bytes(){
+        let mut slots = Vec::new();
+        let mut usages = vec![usage("a", COLOR, 0, 1), usage("b", COLOR, 2, 3), usage("c", HDR, 0, 3)];
+        let assigned = assign_slots(&mut slots, &mut usages);
+
+        let texture = 64 * 64 * 4;
+        assert_eq!(memory_report(&slots, &usages, &assigned), TransientMemoryReport{
+            textures: 3,
+            pooled_textures: 2,
+            allocated_bytes: texture + texture * 2,
+            peak_bytes: texture + texture * 2,
+            unaliased_bytes: texture * 2 + texture * 2,
+        });
+    }
+
+    #[test]
+    fn peak_is_the_most_in_use_at_once(){
+        let mut slots = Vec::new();
+        // Different formats, so nothing aliases, but the HDR textures are never in use together
+        let mut usages = vec![usage("color", COLOR, 0, 1), usage("hdr", HDR, 1, 2), usage("small", HDR, 3, 3)];
+        usages[2].size = size(32, 32);
+        let assigned = assign_slots(&mut slots, &mut usages);
+
+        let report = memory_report(&slots, &usages, &assigned);
+        let (color, hdr, small) = (64 * 64 * 4, 64 * 64 * 8, 32 * 32 * 8);
+        assert_eq!(report.allocated_bytes, color + hdr + small);
+        // Color and hdr overlap at 1, small is alone at 3
+        assert_eq!(report.peak_bytes, color + hdr);
+
+        // A persistent texture is in use all frame
+        usages[2].persistent = true;
+        let mut slots = Vec::new();
+        let assigned = assign_slots(&mut slots, &mut usages);
+        assert_eq!(memory_report(&slots, &usages, &assigned).peak_bytes, color + hdr + small);
+    }
+
+    #[test]
+    fn combined_depth_stencil_sizes(){
+        assert_eq!(texture_bytes(wgpu::TextureFormat::Depth32FloatStencil8, size(2, 2)), 32);
+        assert_eq!(texture_bytes(wgpu::TextureFormat::Depth24PlusStencil8, size(2, 2)), 16);
+    }
+}
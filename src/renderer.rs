@@ -1,15 +1,18 @@
 use crate::surface_wrapper::SurfaceWrapper;
 use crate::{Handle, MutHandle};
-use crate::render_graph::{RenderGraph, RenderNode};
+use crate::render_graph::{BuildReport, GraphDescription, NodeId, RenderGraph, RenderGraphTemplate, RenderNode, SubgraphParameters, TransientMemoryReport, TransientTextureDescriptor};
 use crate::device_handler::DeviceHandler;
 use crate::instance_handler::InstanceHandler;
 use anyhow::Result;
 use log::info;
 use winit::window::CursorGrabMode;
+use std::collections::HashMap;
 use std::sync::Arc;
 use winit::dpi::PhysicalSize;
 use winit::event::{Event, WindowEvent};
-use crate::render_graph::ResourceManager;
+use crate::render_graph::{BuildError, OitTargets, ResourceManager};
+use crate::shader::WgslStruct;
+use crate::types::{Camera, IndirectArgs, StorageBuffer, TransformHandle, Uniform, UniformBuffer};
 
 pub struct Renderer {
     window: Handle<winit::window::Window>,
@@ -61,10 +64,21 @@ impl Renderer {
 
         let render_graph = RenderGraph::new();
 
+        let color_format = surface_wrapper.get_configuration().lock().unwrap().format;
+        // Each depth format supports its own sample counts
+        let depth_formats: HashMap<wgpu::TextureFormat, Vec<u32>> = device_handler.get_depth_formats().into_iter()
+            .map(|format| (format, device_handler.get_supported_sample_counts(&[color_format], Some(format))))
+            .collect();
+        info!("Supported MSAA sample counts by depth format: {:?}", depth_formats);
+        // OIT nodes render into their own targets, so they're limited by those formats too
+        let oit_sample_counts = device_handler.get_supported_sample_counts(&[OitTargets::ACCUM_FORMAT, OitTargets::REVEAL_FORMAT], None);
+
         let resource_manager = ResourceManager::new(
             device_handler.get_device(),
             device_handler.get_queue(),
             surface_wrapper.get_configuration(),
+            depth_formats,
+            oit_sample_counts,
         );
 
         info!("Successfully initialized renderer");
@@ -82,14 +96,16 @@ impl Renderer {
         })
     }
 
-    pub fn initialize(&mut self) {
-        self.render_graph.build(self.resource_manager.clone());
+    pub fn initialize(&mut self) -> Result<()> {
+        self.render_graph.build(self.resource_manager.clone())?;
 
         // Lock the cursor
         self.window.set_cursor_grab(CursorGrabMode::Confined)
             .or_else(|_e| self.window.set_cursor_grab(CursorGrabMode::Locked))
             .unwrap();
         self.window.set_cursor_visible(false);
+
+        Ok(())
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -168,8 +184,153 @@ impl Renderer {
         RenderNode::new(name, self.device_handler.get_device(), self.device_handler.get_queue())
     }
 
-    pub fn add_render_node(&mut self, node: RenderNode) {
-        self.render_graph.add_node(node);
+    // Nodes added after `initialize` are built straight away, and only added if they build
+    pub fn add_render_node(&mut self, node: RenderNode) -> Result<NodeId, BuildReport> {
+        self.render_graph.add_node(node, self.resource_manager.clone())
+    }
+
+    pub fn remove_render_node(&mut self, id: NodeId) -> Option<RenderNode> {
+        self.render_graph.remove_node(id)
+    }
+
+    // Add an instance of a template as one node, with its own camera, target and
+    // viewport. Call it again for each camera or viewport that needs the chain.
+    pub fn instantiate_template(&mut self, template: &RenderGraphTemplate, parameters: SubgraphParameters) -> Result<NodeId, BuildReport> {
+        let subgraph = template.instantiate(self.device_handler.get_device(), self.device_handler.get_queue(),
+                                            self.resource_manager.clone(), parameters);
+        self.render_graph.add_subgraph(subgraph, self.resource_manager.clone())
+    }
+
+    // Change a template instance's camera, target or viewport, rebuilding its nodes
+    pub fn set_subgraph_parameters(&mut self, id: NodeId, parameters: SubgraphParameters) -> Result<(), BuildReport> {
+        self.render_graph.set_subgraph_parameters(id, parameters, self.resource_manager.clone())
+    }
+
+    // Put `node` in place of the node with `id`, returning the old one. The old node
+    // stays if the new one fails to build.
+    pub fn replace_render_node(&mut self, id: NodeId, node: RenderNode) -> Result<Option<RenderNode>, BuildReport> {
+        self.render_graph.replace_node(id, node, self.resource_manager.clone())
+    }
+
+    // Change where a node runs in the graph
+    pub fn move_render_node(&mut self, id: NodeId, index: usize) -> bool {
+        self.render_graph.move_node(id, index)
+    }
+
+    pub fn set_render_node_enabled(&mut self, id: NodeId, enabled: bool) -> bool {
+        self.render_graph.set_node_enabled(id, enabled)
+    }
+
+    // Run an OnDemand node (or the nodes of a template instance) in the next frame
+    pub fn request_node_run(&mut self, id: NodeId) -> bool {
+        self.render_graph.request_run(id)
+    }
+
+    // Set or clear a flag that RunPolicy::WhenFlag nodes run on
+    pub fn set_render_flag(&mut self, flag: &str, set: bool) {
+        self.resource_manager.lock().unwrap().set_render_flag(flag, set);
+    }
+
+    pub fn is_render_node_enabled(&self, id: NodeId) -> Option<bool> {
+        self.render_graph.is_node_enabled(id)
+    }
+
+    pub fn get_render_node_mut(&mut self, id: NodeId) -> Option<&mut RenderNode> {
+        self.render_graph.get_node_mut(id)
+    }
+
+    // Rebuild one node after changing its commands, without touching the others
+    pub fn rebuild_render_node(&mut self, id: NodeId) -> Result<(), BuildReport> {
+        self.render_graph.rebuild_node(id, self.resource_manager.clone())
+    }
+
+    // The render graph in Graphviz DOT, for debugging and docs
+    pub fn render_graph_to_dot(&self) -> String {
+        self.render_graph.to_dot()
+    }
+
+    // Turn on MSAA (2, 4 or 8 samples, 1 turns it off) for every node that doesn't set
+    // its own count. Nodes draw into multisampled targets and resolve into the frame.
+    pub fn set_sample_count(&mut self, count: u32) -> Result<(), BuildError> {
+        self.resource_manager.lock().unwrap().set_sample_count(count)
+    }
+
+    // The sample counts supported with the current depth format (see set_depth_format)
+    pub fn get_supported_sample_counts(&self) -> Vec<u32> {
+        self.resource_manager.lock().unwrap().get_supported_sample_counts().to_vec()
+    }
+
+    // Use a depth format with a stencil aspect (Depth24PlusStencil8, or Depth32FloatStencil8
+    // where supported) so nodes can set a stencil state. The default is Depth32Float.
+    // Fails if the format can't render at the current sample count, so set it first.
+    pub fn set_depth_format(&mut self, format: wgpu::TextureFormat) -> Result<(), BuildError> {
+        self.resource_manager.lock().unwrap().set_depth_format(format)
+    }
+
+    // Sort draws by distance to this camera: transparent nodes back to front, nodes
+    // with sort_front_to_back front to back. Call again whenever the camera moves.
+    pub fn update_sort_camera(&mut self, camera: &Camera) {
+        self.resource_manager.lock().unwrap().set_view_position(camera.position);
+    }
+
+    // Load the nodes described in a .ron or .json file, ready for add_render_node.
+    // Uniform buffers it names must be registered first. Named transforms that aren't
+    // registered yet are registered from the file, for get_transform.
+    pub fn load_render_graph<T: AsRef<std::path::Path>>(&mut self, path: T) -> Result<Vec<RenderNode>> {
+        let description = GraphDescription::load(path)?;
+
+        let mut resource_manager = self.resource_manager.lock().unwrap();
+        let (nodes, new_transforms) = description.create_nodes(self.device_handler.get_device(), self.device_handler.get_queue(),
+                                                               &resource_manager)?;
+        for (name, transform) in new_transforms {
+            resource_manager.register_transform(&name, transform);
+        }
+
+        Ok(nodes)
+    }
+
+    // Let graph descriptions bind `buffer` by name
+    pub fn register_uniform_buffer<T: Uniform>(&mut self, name: &str, buffer: &UniformBuffer<T>) {
+        self.resource_manager.lock().unwrap().register_uniform_buffer(name, buffer);
+    }
+
+    // Let graph descriptions draw with `transform` by name
+    pub fn register_transform(&mut self, name: &str, transform: TransformHandle) {
+        self.resource_manager.lock().unwrap().register_transform(name, transform);
+    }
+
+    // A transform registered by name, or created by a loaded graph description
+    pub fn get_transform(&self, name: &str) -> Option<TransformHandle> {
+        self.resource_manager.lock().unwrap().get_named_transform(name)
+    }
+
+    // Declare an offscreen texture for nodes to render into with render_to_transient
+    // and sample with Command::BindTransient. Textures that are never needed at the
+    // same time in the graph share memory.
+    pub fn declare_transient_texture(&mut self, name: &str, descriptor: TransientTextureDescriptor) {
+        self.resource_manager.lock().unwrap().declare_transient_texture(name, descriptor);
+    }
+
+    // How much memory the transient textures took in the last frame, with and without aliasing
+    pub fn get_transient_memory_report(&self) -> TransientMemoryReport {
+        self.resource_manager.lock().unwrap().get_transient_memory_report()
+    }
+
+    // Create a storage buffer that render nodes can bind with Command::BindStorageBuffer
+    pub fn create_storage_buffer<T: bytemuck::Pod>(&mut self, name: &str, data: &[T], read_only: bool) -> StorageBuffer<T> {
+        self.resource_manager.lock().unwrap().create_storage_buffer(name, data, read_only)
+    }
+
+    // Create a buffer of indirect draw arguments (DrawIndirectArgs or DrawIndexedIndirectArgs)
+    // for Command::DrawIndirect and friends. It can be bound as a storage buffer too, so a
+    // compute pass can write it; pass read_only = false for that.
+    pub fn create_indirect_buffer<T: IndirectArgs>(&mut self, name: &str, args: &[T], read_only: bool) -> StorageBuffer<T> {
+        self.resource_manager.lock().unwrap().create_indirect_buffer(name, args, read_only)
+    }
+
+    // Let shaders `#include "<WGSL_NAME>.wgsl"` to get the struct declaration for `T`
+    pub fn add_shader_struct<T: WgslStruct>(&mut self) {
+        self.resource_manager.lock().unwrap().add_shader_struct::<T>();
     }
 
     pub fn get_surface_configuration(&self) -> MutHandle<wgpu::SurfaceConfiguration> {
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+/// Compiled shader variants by `ShaderPermutation::key()`, along with the
+/// preprocessed source each was compiled from.
+///
+/// Lookups go by the source as well as the key, so a variant whose file (or any
+/// file it includes) was edited since it was compiled is compiled again - which is
+/// what lets `rebuild_node` pick up shader edits.
+pub(crate) struct ShaderCache<T>{
+    entries: HashMap<String, (String, T)>,
+}
+
+impl<T: Clone> ShaderCache<T>{
+    pub fn new() -> Self{
+        Self{
+            entries: HashMap::new(),
+        }
+    }
+
+    // The variant compiled from exactly `source`, compiling (and replacing any stale one) if there isn't one
+    pub fn get_or_compile<E>(&mut self, key: &str, source: &str, compile: impl FnOnce() -> Result<T, E>) -> Result<T, E>{
+        if let Some((cached_source, shader)) = self.entries.get(key){
+            if cached_source == source{
+                return Ok(shader.clone());
+            }
+        }
+
+        let shader = compile()?;
+        self.entries.insert(key.to_string(), (source.to_string(), shader.clone()));
+        Ok(shader)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::shader::{ShaderPermutation, ShaderPreprocessor};
+
+    // Preprocess the variant and look it up, counting how often it's compiled
+    fn load(cache: &mut ShaderCache<usize>, permutation: &ShaderPermutation, compiles: &mut usize) -> usize{
+        let source = permutation.preprocessor(&ShaderPreprocessor::new()).process_file_with_map(permutation.path()).unwrap();
+        cache.get_or_compile(&permutation.key(), &source.source, || -> Result<usize, ()>{
+            *compiles += 1;
+            Ok(*compiles)
+        }).unwrap()
+    }
+
+    #[test]
+    fn edited_sources_are_compiled_again(){
+        let dir = std::env::temp_dir().join(format!("minirender_shader_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("common.wgsl"), "const SCALE: f32 = 1.0;").unwrap();
+        std::fs::write(dir.join("main.wgsl"), "#include \"common.wgsl\"\nmain").unwrap();
+
+        let permutation = ShaderPermutation::new(dir.join("main.wgsl").to_str().unwrap());
+        let mut cache = ShaderCache::new();
+        let mut compiles = 0;
+
+        assert_eq!(load(&mut cache, &permutation, &mut compiles), 1);
+        // Unchanged, so the compiled variant is reused
+        assert_eq!(load(&mut cache, &permutation, &mut compiles), 1);
+
+        std::fs::write(dir.join("main.wgsl"), "#include \"common.wgsl\"\nedited").unwrap();
+        assert_eq!(load(&mut cache, &permutation, &mut compiles), 2);
+
+        // Edits to included files count too
+        std::fs::write(dir.join("common.wgsl"), "const SCALE: f32 = 2.0;").unwrap();
+        assert_eq!(load(&mut cache, &permutation, &mut compiles), 3);
+
+        // Other variants of the same file are cached separately
+        assert_eq!(load(&mut cache, &permutation.clone().with_define("INSTANCED"), &mut compiles), 4);
+        assert_eq!(load(&mut cache, &permutation, &mut compiles), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_compiles_are_not_cached(){
+        let mut cache: ShaderCache<usize> = ShaderCache::new();
+
+        assert!(cache.get_or_compile("shader.wgsl[]", "broken", || Err("parse error")).is_err());
+        assert_eq!(cache.get_or_compile("shader.wgsl[]", "broken", || Ok::<_, &str>(1)), Ok(1));
+    }
+}
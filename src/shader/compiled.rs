@@ -0,0 +1,41 @@
+use wgpu::naga;
+
+use super::{PreprocessedShader, ShaderDiagnostic, ShaderReflection};
+
+/// A shader module along with the bindings naga found in it.
+pub struct CompiledShader{
+    pub module: wgpu::ShaderModule,
+    pub reflection: ShaderReflection,
+}
+
+impl CompiledShader{
+    pub fn new(device: &wgpu::Device, label: &str, source: PreprocessedShader) -> Result<Self, ShaderDiagnostic>{
+        // Parse and validate with naga first, so errors point at the right file and line
+        // rather than surfacing as a device panic
+        let module = naga::front::wgsl::parse_str(&source.source)
+            .map_err(|e| ShaderDiagnostic::from_parse_error(label, &e, &source))?;
+
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .map_err(|e| ShaderDiagnostic::from_validation_error(label, &e, &source))?;
+
+        let reflection = ShaderReflection::new(&module, &info);
+
+        // Anything naga accepted but the device doesn't is caught here
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.source.into()),
+        });
+
+        if let Some(error) = pollster::block_on(device.pop_error_scope()){
+            return Err(ShaderDiagnostic::new(label, error.to_string()));
+        }
+
+        Ok(Self{
+            module,
+            reflection,
+        })
+    }
+}
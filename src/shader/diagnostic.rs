@@ -0,0 +1,106 @@
+use std::fmt;
+
+use wgpu::naga;
+
+use super::PreprocessedShader;
+
+/// A span of shader source an error points at, mapped back to the original file.
+#[derive(Clone, Debug)]
+pub struct DiagnosticLabel{
+    pub file: String,
+    // 1-based line and column in `file`
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub message: String,
+    // The text of the offending line
+    pub source_line: String,
+}
+
+/// A readable shader error: what went wrong and where, in terms of the files
+/// the user wrote rather than the preprocessed source naga saw.
+#[derive(Clone, Debug)]
+pub struct ShaderDiagnostic{
+    pub shader: String,
+    pub message: String,
+    pub labels: Vec<DiagnosticLabel>,
+}
+
+impl ShaderDiagnostic{
+    pub fn new<S: Into<String>, M: Into<String>>(shader: S, message: M) -> Self{
+        Self{
+            shader: shader.into(),
+            message: message.into(),
+            labels: Vec::new(),
+        }
+    }
+
+    pub(crate) fn from_parse_error(shader: &str, error: &naga::front::wgsl::ParseError, source: &PreprocessedShader) -> Self{
+        let mut diagnostic = Self::new(shader, error.message());
+        for (span, message) in error.labels(){
+            diagnostic.add_label(span, message, source);
+        }
+        diagnostic
+    }
+
+    pub(crate) fn from_validation_error(shader: &str, error: &naga::WithSpan<naga::valid::ValidationError>,
+                                        source: &PreprocessedShader) -> Self{
+        // The interesting detail is usually further down the error chain
+        let mut message = error.to_string();
+        let mut cause = std::error::Error::source(error);
+        while let Some(inner) = cause{
+            message.push_str(&format!(": {}", inner));
+            cause = inner.source();
+        }
+
+        let mut diagnostic = Self::new(shader, message);
+        for (span, label) in error.spans(){
+            diagnostic.add_label(*span, label, source);
+        }
+        diagnostic
+    }
+
+    fn add_label(&mut self, span: naga::Span, message: &str, source: &PreprocessedShader){
+        if !span.is_defined(){
+            return;
+        }
+
+        let location = span.location(&source.source);
+        let line = location.line_number as usize;
+        let (file, file_line) = source.locate(line).unwrap_or((self.shader.as_str(), line));
+
+        let source_line = source.source.lines().nth(line - 1).unwrap_or("").to_string();
+        let column = location.line_position as usize;
+
+        // Spans can run over several lines, only underline the first
+        let length = (location.length as usize).min(source_line.len().saturating_sub(column - 1));
+
+        self.labels.push(DiagnosticLabel{
+            file: file.to_string(),
+            line: file_line,
+            column,
+            length,
+            message: message.to_string(),
+            source_line,
+        });
+    }
+}
+
+impl fmt::Display for ShaderDiagnostic{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        writeln!(f, "error in {}: {}", self.shader, self.message)?;
+
+        for label in self.labels.iter(){
+            let gutter = " ".repeat(label.line.to_string().len());
+            writeln!(f, "{}--> {}:{}:{}", gutter, label.file, label.line, label.column)?;
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", label.line, label.source_line)?;
+            writeln!(f, "{} | {}{} {}", gutter, " ".repeat(label.column.saturating_sub(1)),
+                     "^".repeat(label.length.max(1)), label.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ShaderDiagnostic{}
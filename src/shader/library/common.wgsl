@@ -0,0 +1,44 @@
+// Shared colour helpers used by the example shaders.
+
+fn aces_tonemapping(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return (color * (color * a + b)) / (color * (color * c + d) + e);
+}
+
+// Filters
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    return pow(color, vec3<f32>(1.0 / 2.2));
+}
+
+fn invert(color: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(1.0) - color;
+}
+
+fn grayscale(color: vec3<f32>) -> vec3<f32> {
+    let average = (color.r + color.g + color.b) / 3.0;
+    return vec3<f32>(average);
+}
+
+fn sepia(color: vec3<f32>) -> vec3<f32> {
+    let r = color.r * 0.393 + color.g * 0.769 + color.b * 0.189;
+    let g = color.r * 0.349 + color.g * 0.686 + color.b * 0.168;
+    let b = color.r * 0.272 + color.g * 0.534 + color.b * 0.131;
+    return vec3<f32>(r, g, b);
+}
+
+fn brightness(color: vec3<f32>, value: f32) -> vec3<f32> {
+    return color + vec3<f32>(value);
+}
+
+fn contrast(color: vec3<f32>, value: f32) -> vec3<f32> {
+    return (color - vec3<f32>(0.5)) * value + vec3<f32>(0.5);
+}
+
+fn saturation(color: vec3<f32>, value: f32) -> vec3<f32> {
+    let average = (color.r + color.g + color.b) / 3.0;
+    return vec3<f32>(average) + (color - vec3<f32>(average)) * value;
+}
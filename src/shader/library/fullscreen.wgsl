@@ -0,0 +1,17 @@
+// A triangle covering the whole screen from three vertices and no vertex buffer:
+// `Command::Draw { vertices: 0..3, instances: 0..1 }` in a node without the Vertex layout.
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+fn fullscreen_vertex(vertex_index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+
+    return out;
+}
@@ -0,0 +1,19 @@
+// Weighted blended order-independent transparency (McGuire & Bavoil, 2013).
+// In a node with order_independent_transparency(true), `#ifdef OIT` return
+// `oit_output(color, in.clip_position.z)` from the fragment shader instead of the color.
+
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) reveal: f32,
+};
+
+fn oit_output(color: vec4<f32>, depth: f32) -> OitOutput {
+    // Nearer and more opaque fragments count for more in the weighted average
+    let weight = clamp(color.a * 3e3 * pow(1.0 - depth, 3.0), 1e-2, 3e3);
+
+    var out: OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.reveal = color.a;
+
+    return out;
+}
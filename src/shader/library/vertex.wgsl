@@ -0,0 +1,24 @@
+// Vertex inputs matching `Vertex::desc()` and `Instance::desc()`.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+};
+
+struct InstanceInput {
+    @builtin(instance_index) instance_index: u32,
+    @location(3) model_matrix_0: vec4<f32>,
+    @location(4) model_matrix_1: vec4<f32>,
+    @location(5) model_matrix_2: vec4<f32>,
+    @location(6) model_matrix_3: vec4<f32>,
+};
+
+fn instance_model_matrix(inst: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        inst.model_matrix_0,
+        inst.model_matrix_1,
+        inst.model_matrix_2,
+        inst.model_matrix_3
+    );
+}
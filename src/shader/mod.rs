@@ -0,0 +1,15 @@
+mod cache;
+mod compiled;
+mod diagnostic;
+mod permutation;
+mod preprocessor;
+mod reflection;
+mod wgsl_struct;
+
+pub(crate) use cache::ShaderCache;
+pub use compiled::CompiledShader;
+pub use diagnostic::{DiagnosticLabel, ShaderDiagnostic};
+pub use permutation::ShaderPermutation;
+pub use preprocessor::{PreprocessedShader, ShaderPreprocessor};
+pub use reflection::{BindingKind, BindingMismatch, ProvidedBinding, ReflectedBinding, ShaderReflection};
+pub use wgsl_struct::WgslStruct;
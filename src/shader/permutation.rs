@@ -0,0 +1,70 @@
+use std::collections::BTreeSet;
+
+use super::ShaderPreprocessor;
+
+/// A single compiled variant of a shader source: the file plus the set of
+/// feature defines it was preprocessed with.
+///
+/// Two permutations with the same path and defines share one `wgpu::ShaderModule`
+/// in the `ResourceManager`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderPermutation{
+    path: String,
+    defines: BTreeSet<String>,
+}
+
+impl ShaderPermutation{
+    // Feature defines the render node sets automatically from its commands
+    pub const INSTANCED: &'static str = "INSTANCED";
+    pub const TEXTURED: &'static str = "TEXTURED";
+    pub const DEPTH_ONLY: &'static str = "DEPTH_ONLY";
+    // Set when the node declares push constants and the device supports them
+    pub const PUSH_CONSTANTS: &'static str = "PUSH_CONSTANTS";
+    // Set when the node's DrawMesh commands carry their own transforms
+    pub const DRAW_TRANSFORM: &'static str = "DRAW_TRANSFORM";
+    // Set for nodes rendering with weighted blended order-independent transparency
+    pub const OIT: &'static str = "OIT";
+
+    pub fn new<T: Into<String>>(path: T) -> Self{
+        Self{
+            path: path.into(),
+            defines: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_define<T: Into<String>>(mut self, define: T) -> Self{
+        self.define(define);
+        self
+    }
+
+    pub fn define<T: Into<String>>(&mut self, define: T){
+        self.defines.insert(define.into());
+    }
+
+    pub fn path(&self) -> &str{
+        &self.path
+    }
+
+    pub fn defines(&self) -> &BTreeSet<String>{
+        &self.defines
+    }
+
+    pub fn has_define(&self, define: &str) -> bool{
+        self.defines.contains(define)
+    }
+
+    /// A stable name for this variant, e.g. `shaders/cube.wgsl[INSTANCED,TEXTURED]`.
+    pub fn key(&self) -> String{
+        let defines: Vec<&str> = self.defines.iter().map(|d| d.as_str()).collect();
+        format!("{}[{}]", self.path, defines.join(","))
+    }
+
+    // A copy of `base` (which carries any extra include sources) with this variant's defines
+    pub fn preprocessor(&self, base: &ShaderPreprocessor) -> ShaderPreprocessor{
+        let mut preprocessor = base.clone();
+        for define in self.defines.iter(){
+            preprocessor.define(define.as_str(), "");
+        }
+        preprocessor
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::types::{CameraUniform, TransformUniform};
+
+use super::WgslStruct;
+
+// Shader library that ships with the renderer. These are resolved by name when
+// an include can't be found next to the including shader, or always when the
+// include uses angle brackets (`#include <common.wgsl>`).
+const BUILTIN_LIBRARY: &[(&str, &str)] = &[
+    ("common.wgsl", include_str!("library/common.wgsl")),
+    ("vertex.wgsl", include_str!("library/vertex.wgsl")),
+    ("fullscreen.wgsl", include_str!("library/fullscreen.wgsl")),
+    ("oit.wgsl", include_str!("library/oit.wgsl")),
+];
+
+/// A small C-style preprocessor for WGSL.
+///
+/// Supports `#include "file"` (relative to the including shader, falling back to the
+/// built-in library), `#include <file>` (built-in library only), `#define NAME [value]`,
+/// `#undef NAME`, and `#ifdef`/`#ifndef`/`#else`/`#endif` blocks.
+///
+/// Extra in-memory sources can be added with `add_source`, and are included like built-ins.
+/// Every file is only included once, so shared headers can be pulled in from several places.
+#[derive(Clone)]
+pub struct ShaderPreprocessor{
+    defines: HashMap<String, String>,
+    sources: HashMap<String, String>,
+}
+
+impl Default for ShaderPreprocessor{
+    fn default() -> Self{
+        let mut preprocessor = Self{
+            defines: HashMap::new(),
+            sources: HashMap::new(),
+        };
+
+        // The renderer's own uniforms are declared from their Rust types
+        preprocessor.add_source("camera.wgsl", CameraUniform::wgsl_struct());
+        preprocessor.add_source("transform.wgsl", TransformUniform::wgsl_struct());
+
+        preprocessor
+    }
+}
+
+// Where an include came from - used to resolve nested includes and to stop a file
+// being pasted twice.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum SourceId{
+    File(PathBuf),
+    Builtin(&'static str),
+    Source(String),
+}
+
+impl SourceId{
+    fn name(&self) -> String{
+        match self{
+            SourceId::File(path) => path.display().to_string(),
+            SourceId::Builtin(name) => format!("<{}>", name),
+            SourceId::Source(name) => format!("<{}>", name),
+        }
+    }
+}
+
+/// Preprocessed WGSL, along with where each of its lines came from.
+#[derive(Clone, Default)]
+pub struct PreprocessedShader{
+    pub source: String,
+    files: Vec<String>,
+    // (index into files, 1-based line) for every output line
+    lines: Vec<(usize, usize)>,
+}
+
+impl PreprocessedShader{
+    /// Map a 1-based line of the preprocessed source back to its file and line.
+    pub fn locate(&self, line: usize) -> Option<(&str, usize)>{
+        let (file, line) = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[*file], *line))
+    }
+
+    fn push_line(&mut self, text: &str, id: &SourceId, line: usize){
+        let name = id.name();
+        let file = match self.files.iter().position(|f| *f == name){
+            Some(file) => file,
+            None => {
+                self.files.push(name);
+                self.files.len() - 1
+            }
+        };
+
+        self.source.push_str(text);
+        self.source.push('\n');
+        self.lines.push((file, line));
+    }
+}
+
+struct Conditional{
+    // Whether the enclosing block was emitting when this one started
+    parent_active: bool,
+    // Whether this branch is currently emitting
+    active: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+impl ShaderPreprocessor{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    /// Look up a file from the built-in shader library.
+    pub fn builtin(name: &str) -> Option<&'static str>{
+        BUILTIN_LIBRARY.iter()
+            .find(|(builtin_name, _)| *builtin_name == name)
+            .map(|(_, source)| *source)
+    }
+
+    pub fn define<K: Into<String>, V: Into<String>>(&mut self, name: K, value: V) -> &mut Self{
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    /// Add an in-memory file that shaders can include by `name`.
+    pub fn add_source<K: Into<String>, V: Into<String>>(&mut self, name: K, source: V) -> &mut Self{
+        self.sources.insert(name.into(), source.into());
+        self
+    }
+
+    /// Make a Rust type's WGSL declaration includable as `<WGSL_NAME>.wgsl`, along
+    /// with the structs it includes.
+    pub fn add_struct<T: WgslStruct>(&mut self) -> &mut Self{
+        T::register(self);
+        self
+    }
+
+    pub fn undefine(&mut self, name: &str) -> &mut Self{
+        self.defines.remove(name);
+        self
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool{
+        self.defines.contains_key(name)
+    }
+
+    pub fn process_file<T: AsRef<Path>>(&self, path: T) -> Result<String>{
+        Ok(self.process_file_with_map(path)?.source)
+    }
+
+    /// Like `process_file`, but keeps track of which file and line each output line came from.
+    pub fn process_file_with_map<T: AsRef<Path>>(&self, path: T) -> Result<PreprocessedShader>{
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read shader {}: {}", path.display(), e))?;
+
+        self.process(&source, SourceId::File(path.to_path_buf()))
+    }
+
+    /// Preprocess a shader held in memory. Relative includes are resolved against `base_dir`.
+    pub fn process_str<T: AsRef<Path>>(&self, source: &str, base_dir: T) -> Result<String>{
+        Ok(self.process(source, SourceId::File(base_dir.as_ref().join("<memory>")))?.source)
+    }
+
+    fn process(&self, source: &str, id: SourceId) -> Result<PreprocessedShader>{
+        let mut defines = self.defines.clone();
+        let mut included = HashSet::new();
+        let mut output = PreprocessedShader::default();
+
+        included.insert(id.clone());
+        self.process_source(source, &id, &mut defines, &mut included, &mut output)?;
+
+        Ok(output)
+    }
+
+    fn process_source(&self, source: &str, id: &SourceId, defines: &mut HashMap<String, String>,
+                      included: &mut HashSet<SourceId>, output: &mut PreprocessedShader) -> Result<()>{
+        let mut conditionals: Vec<Conditional> = Vec::new();
+
+        for (line_idx, line) in source.lines().enumerate(){
+            let line_number = line_idx + 1;
+            let location = || format!("{}:{}", id.name(), line_number);
+
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let trimmed = line.trim_start();
+
+            if !trimmed.starts_with('#'){
+                if active{
+                    output.push_line(&Self::substitute(line, defines), id, line_number);
+                }else{
+                    output.push_line("", id, line_number);
+                }
+                continue;
+            }
+
+            let mut parts = trimmed[1..].splitn(2, char::is_whitespace);
+            let directive = parts.next().unwrap_or("");
+            let argument = parts.next().unwrap_or("").trim();
+
+            match directive{
+                "ifdef" | "ifndef" => {
+                    let name = Self::expect_name(argument, directive, &location)?;
+                    let defined = defines.contains_key(name);
+                    conditionals.push(Conditional{
+                        parent_active: active,
+                        active: active && (defined == (directive == "ifdef")),
+                        seen_else: false,
+                        line: line_number,
+                    });
+                }
+                "else" => {
+                    let conditional = conditionals.last_mut()
+                        .ok_or_else(|| anyhow!("{}: #else without #ifdef", location()))?;
+                    if conditional.seen_else{
+                        bail!("{}: duplicate #else", location());
+                    }
+                    conditional.seen_else = true;
+                    conditional.active = conditional.parent_active && !conditional.active;
+                }
+                "endif" => {
+                    conditionals.pop()
+                        .ok_or_else(|| anyhow!("{}: #endif without #ifdef", location()))?;
+                }
+                _ if !active => {
+                    // Directives inside a disabled block are skipped entirely
+                }
+                "define" => {
+                    let mut parts = argument.splitn(2, char::is_whitespace);
+                    let name = Self::expect_name(parts.next().unwrap_or(""), directive, &location)?;
+                    let value = parts.next().unwrap_or("").trim();
+                    defines.insert(name.to_string(), value.to_string());
+                }
+                "undef" => {
+                    let name = Self::expect_name(argument, directive, &location)?;
+                    defines.remove(name);
+                }
+                "include" => {
+                    let include = self.resolve_include(argument, id)
+                        .map_err(|e| anyhow!("{}: {}", location(), e))?;
+
+                    if included.insert(include.clone()){
+                        let source = match &include{
+                            SourceId::File(path) => std::fs::read_to_string(path)
+                                .map_err(|e| anyhow!("{}: failed to read {}: {}", location(), path.display(), e))?,
+                            SourceId::Builtin(name) => Self::builtin(name).unwrap().to_string(),
+                            SourceId::Source(name) => self.sources[name].clone(),
+                        };
+
+                        self.process_source(&source, &include, defines, included, output)?;
+                    }
+                }
+                _ => bail!("{}: unknown directive #{}", location(), directive),
+            }
+
+            // Directives become blank lines so the surrounding code keeps its layout
+            output.push_line("", id, line_number);
+        }
+
+        if let Some(conditional) = conditionals.last(){
+            bail!("{}:{}: unterminated #ifdef", id.name(), conditional.line);
+        }
+
+        Ok(())
+    }
+
+    fn resolve_include(&self, argument: &str, from: &SourceId) -> Result<SourceId>{
+        if let Some(name) = argument.strip_prefix('<').and_then(|a| a.strip_suffix('>')){
+            if self.sources.contains_key(name){
+                return Ok(SourceId::Source(name.to_string()));
+            }
+
+            return Self::builtin(name)
+                .map(|_| Self::builtin_id(name))
+                .ok_or_else(|| anyhow!("no built-in shader named {}", name));
+        }
+
+        let name = argument.strip_prefix('"').and_then(|a| a.strip_suffix('"'))
+            .ok_or_else(|| anyhow!("expected #include \"file\" or #include <file>, got {}", argument))?;
+
+        // Try next to the including file first, then the built-in library
+        if let SourceId::File(path) = from{
+            let candidate = path.parent().unwrap_or(Path::new("")).join(name);
+            if candidate.is_file(){
+                return Ok(SourceId::File(candidate));
+            }
+        }
+
+        if self.sources.contains_key(name){
+            return Ok(SourceId::Source(name.to_string()));
+        }
+
+        if Self::builtin(name).is_some(){
+            return Ok(Self::builtin_id(name));
+        }
+
+        bail!("could not find include {}", name)
+    }
+
+    fn builtin_id(name: &str) -> SourceId{
+        let (name, _) = BUILTIN_LIBRARY.iter().find(|(builtin_name, _)| *builtin_name == name).unwrap();
+        SourceId::Builtin(name)
+    }
+
+    fn expect_name<'a>(argument: &'a str, directive: &str, location: &dyn Fn() -> String) -> Result<&'a str>{
+        let valid = argument.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && argument.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !valid{
+            bail!("{}: #{} expects an identifier, got '{}'", location(), directive, argument);
+        }
+
+        Ok(argument)
+    }
+
+    // Replace any identifier that has a non-empty #define value
+    fn substitute(line: &str, defines: &HashMap<String, String>) -> String{
+        if defines.values().all(|value| value.is_empty()){
+            return line.to_string();
+        }
+
+        let mut result = String::with_capacity(line.len());
+        let mut identifier = String::new();
+
+        let flush = |identifier: &mut String, result: &mut String| {
+            match defines.get(identifier.as_str()){
+                Some(value) if !value.is_empty() => result.push_str(value),
+                _ => result.push_str(identifier),
+            }
+            identifier.clear();
+        };
+
+        for c in line.chars(){
+            if c.is_ascii_alphanumeric() || c == '_'{
+                identifier.push(c);
+            }else{
+                flush(&mut identifier, &mut result);
+                result.push(c);
+            }
+        }
+        flush(&mut identifier, &mut result);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // Output lines with the blank lines left by directives and disabled blocks removed
+    fn code_lines(source: &str) -> Vec<String>{
+        source.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect()
+    }
+
+    fn process(preprocessor: &ShaderPreprocessor, source: &str) -> Result<Vec<String>>{
+        Ok(code_lines(&preprocessor.process_str(source, ".")?))
+    }
+
+    #[test]
+    fn ifdef_picks_the_defined_branch(){
+        let source = "#ifdef SHADOWS\nshadowed\n#else\nunshadowed\n#endif\nalways";
+
+        let mut preprocessor = ShaderPreprocessor::new();
+        assert_eq!(process(&preprocessor, source).unwrap(), ["unshadowed", "always"]);
+
+        preprocessor.define("SHADOWS", "");
+        assert_eq!(process(&preprocessor, source).unwrap(), ["shadowed", "always"]);
+    }
+
+    #[test]
+    fn ifndef_and_nested_blocks(){
+        let source = "#ifndef A\nnot_a\n#else\n#ifdef B\na_and_b\n#else\na_only\n#endif\n#endif";
+
+        let mut preprocessor = ShaderPreprocessor::new();
+        assert_eq!(process(&preprocessor, source).unwrap(), ["not_a"]);
+
+        preprocessor.define("A", "");
+        assert_eq!(process(&preprocessor, source).unwrap(), ["a_only"]);
+
+        preprocessor.define("B", "");
+        assert_eq!(process(&preprocessor, source).unwrap(), ["a_and_b"]);
+    }
+
+    #[test]
+    fn directives_in_disabled_blocks_are_ignored(){
+        let source = "#ifdef MISSING\n#define INNER\n#include \"does_not_exist.wgsl\"\n#endif\n#ifdef INNER\ninner\n#endif";
+        assert!(process(&ShaderPreprocessor::new(), source).unwrap().is_empty());
+    }
+
+    #[test]
+    fn defines_with_values_are_substituted(){
+        let source = "#define LIGHTS 4\nvar<uniform> lights: array<Light, LIGHTS>;\nlet MAX_LIGHTS = 1;";
+        assert_eq!(process(&ShaderPreprocessor::new(), source).unwrap(),
+                   ["var<uniform> lights: array<Light, 4>;", "let MAX_LIGHTS = 1;"]);
+
+        let source = "#define LIGHTS 4\n#undef LIGHTS\nLIGHTS";
+        assert_eq!(process(&ShaderPreprocessor::new(), source).unwrap(), ["LIGHTS"]);
+    }
+
+    #[test]
+    fn includes_are_pasted_once(){
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.add_source("header.wgsl", "#define FROM_HEADER\nheader");
+        preprocessor.add_source("other.wgsl", "#include <header.wgsl>\nother");
+
+        let source = "#include \"header.wgsl\"\n#include <other.wgsl>\n#include <header.wgsl>\n#ifdef FROM_HEADER\nmain\n#endif";
+        assert_eq!(process(&preprocessor, source).unwrap(), ["header", "other", "main"]);
+    }
+
+    #[test]
+    fn includes_resolve_next_to_the_shader_first(){
+        let dir = std::env::temp_dir().join(format!("minirender_preprocessor_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("common.wgsl"), "local_common").unwrap();
+        std::fs::write(dir.join("main.wgsl"), "#include \"common.wgsl\"\n#include <common.wgsl>\nmain").unwrap();
+
+        let shader = ShaderPreprocessor::new().process_file_with_map(dir.join("main.wgsl")).unwrap();
+        let lines = code_lines(&shader.source);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The quoted include finds the local file, the angle bracket one the built-in library
+        assert_eq!(lines.first().map(String::as_str), Some("local_common"));
+        assert_eq!(lines.last().map(String::as_str), Some("main"));
+        assert!(lines.len() > 2);
+
+        let (file, line) = shader.locate(1).unwrap();
+        assert!(file.ends_with("common.wgsl"));
+        assert_eq!(line, 1);
+
+        let last = shader.source.lines().count();
+        let (file, line) = shader.locate(last).unwrap();
+        assert!(file.ends_with("main.wgsl"));
+        assert_eq!(line, 3);
+    }
+
+    #[test]
+    fn malformed_blocks_are_errors(){
+        let preprocessor = ShaderPreprocessor::new();
+
+        for source in ["#ifdef A\nunterminated", "#else", "#endif", "#ifdef A\n#else\n#else\n#endif",
+                       "#ifdef 1A\n#endif", "#pragma once", "#include <missing.wgsl>", "#include missing.wgsl"]{
+            assert!(process(&preprocessor, source).is_err(), "{:?} should fail", source);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt;
+
+use wgpu::naga;
+
+/// What kind of resource a binding holds, as seen by either the shader or the node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BindingKind{
+    UniformBuffer{ size: u64 },
+    StorageBuffer{ size: u64, read_only: bool },
+    Texture{
+        sample_type: wgpu::TextureSampleType,
+        view_dimension: wgpu::TextureViewDimension,
+        multisampled: bool,
+    },
+    StorageTexture{ view_dimension: wgpu::TextureViewDimension },
+    Sampler{ comparison: bool },
+    AccelerationStructure,
+}
+
+impl BindingKind{
+    /// Describe what a node provides from the layout entry it built.
+    pub fn from_layout_entry(entry: &wgpu::BindGroupLayoutEntry) -> Self{
+        match entry.ty{
+            wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, min_binding_size, .. } => {
+                BindingKind::UniformBuffer{ size: min_binding_size.map_or(0, |size| size.get()) }
+            }
+            wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only }, min_binding_size, .. } => {
+                BindingKind::StorageBuffer{ size: min_binding_size.map_or(0, |size| size.get()), read_only }
+            }
+            wgpu::BindingType::Texture { sample_type, view_dimension, multisampled } => {
+                BindingKind::Texture{ sample_type, view_dimension, multisampled }
+            }
+            wgpu::BindingType::StorageTexture { view_dimension, .. } => {
+                BindingKind::StorageTexture{ view_dimension }
+            }
+            wgpu::BindingType::Sampler(ty) => {
+                BindingKind::Sampler{ comparison: ty == wgpu::SamplerBindingType::Comparison }
+            }
+            wgpu::BindingType::AccelerationStructure => BindingKind::AccelerationStructure,
+        }
+    }
+
+    // Check a provided resource against what the shader expects.
+    // Sizes of 0 mean "unknown" and are not checked.
+    fn check(&self, provided: &BindingKind) -> Result<(), String>{
+        match (self, provided){
+            (BindingKind::UniformBuffer{ size: expected }, BindingKind::UniformBuffer{ size: provided }) |
+            (BindingKind::StorageBuffer{ size: expected, .. }, BindingKind::StorageBuffer{ size: provided, .. })
+                if *provided != 0 && provided < expected => {
+                Err(format!("buffer is {} bytes but the shader expects at least {} bytes", provided, expected))
+            }
+            (BindingKind::UniformBuffer{ .. }, BindingKind::UniformBuffer{ .. }) => Ok(()),
+            (BindingKind::StorageBuffer{ read_only: expected, .. }, BindingKind::StorageBuffer{ read_only: provided, .. }) => {
+                // A read-write binding can back a read-only variable, but not the other way round
+                if !*expected && *provided{
+                    Err("shader writes to the buffer but it is bound read-only".to_string())
+                }else{
+                    Ok(())
+                }
+            }
+            (BindingKind::Texture{ sample_type: expected_type, view_dimension: expected_dimension, multisampled: expected_ms },
+             BindingKind::Texture{ sample_type: provided_type, view_dimension: provided_dimension, multisampled: provided_ms }) => {
+                // naga can't tell whether a float texture is filterable, so only compare the kind of sample
+                if std::mem::discriminant(expected_type) != std::mem::discriminant(provided_type){
+                    Err(format!("texture samples {:?} but the shader expects {:?}", provided_type, expected_type))
+                }else if expected_dimension != provided_dimension{
+                    Err(format!("texture is {:?} but the shader expects {:?}", provided_dimension, expected_dimension))
+                }else if expected_ms != provided_ms{
+                    Err(format!("texture multisampling is {} but the shader expects {}", provided_ms, expected_ms))
+                }else{
+                    Ok(())
+                }
+            }
+            (BindingKind::StorageTexture{ view_dimension: expected }, BindingKind::StorageTexture{ view_dimension: provided }) => {
+                if expected != provided{
+                    Err(format!("storage texture is {:?} but the shader expects {:?}", provided, expected))
+                }else{
+                    Ok(())
+                }
+            }
+            (BindingKind::Sampler{ comparison: expected }, BindingKind::Sampler{ comparison: provided }) => {
+                if expected != provided{
+                    Err(format!("sampler comparison is {} but the shader expects {}", provided, expected))
+                }else{
+                    Ok(())
+                }
+            }
+            (BindingKind::AccelerationStructure, BindingKind::AccelerationStructure) => Ok(()),
+            _ => Err(format!("node provides a {} but the shader expects a {}", provided, self)),
+        }
+    }
+}
+
+impl fmt::Display for BindingKind{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            BindingKind::UniformBuffer{ .. } => write!(f, "uniform buffer"),
+            BindingKind::StorageBuffer{ read_only: true, .. } => write!(f, "read-only storage buffer"),
+            BindingKind::StorageBuffer{ read_only: false, .. } => write!(f, "read-write storage buffer"),
+            BindingKind::Texture{ .. } => write!(f, "texture"),
+            BindingKind::StorageTexture{ .. } => write!(f, "storage texture"),
+            BindingKind::Sampler{ .. } => write!(f, "sampler"),
+            BindingKind::AccelerationStructure => write!(f, "acceleration structure"),
+        }
+    }
+}
+
+/// A resource binding declared by a shader.
+#[derive(Clone, Debug)]
+pub struct ReflectedBinding{
+    pub group: u32,
+    pub binding: u32,
+    pub name: String,
+    pub kind: BindingKind,
+    // The stages whose entry points actually use this binding
+    pub stages: wgpu::ShaderStages,
+}
+
+/// A resource binding that a render node provides.
+#[derive(Clone, Debug)]
+pub struct ProvidedBinding{
+    pub group: u32,
+    pub binding: u32,
+    pub kind: BindingKind,
+    pub visibility: wgpu::ShaderStages,
+}
+
+impl ProvidedBinding{
+    pub fn from_layout_entry(group: u32, entry: &wgpu::BindGroupLayoutEntry) -> Self{
+        Self{
+            group,
+            binding: entry.binding,
+            kind: BindingKind::from_layout_entry(entry),
+            visibility: entry.visibility,
+        }
+    }
+}
+
+/// A mismatch between what a shader expects at a group/binding and what the node provides.
+#[derive(Clone, Debug)]
+pub struct BindingMismatch{
+    pub group: u32,
+    pub binding: u32,
+    // The shader variable, if the shader declares this binding
+    pub name: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for BindingMismatch{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match &self.name{
+            Some(name) => write!(f, "@group({}) @binding({}) `{}`: {}", self.group, self.binding, name, self.message),
+            None => write!(f, "@group({}) @binding({}): {}", self.group, self.binding, self.message),
+        }
+    }
+}
+
+/// The resource bindings a shader module declares, found with naga.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection{
+    pub bindings: Vec<ReflectedBinding>,
+}
+
+impl ShaderReflection{
+    pub fn new(module: &naga::Module, info: &naga::valid::ModuleInfo) -> Self{
+        let mut bindings = Vec::new();
+
+        for (handle, variable) in module.global_variables.iter(){
+            let Some(binding) = &variable.binding else { continue };
+
+            let kind = match Self::binding_kind(module, variable){
+                Some(kind) => kind,
+                None => continue,
+            };
+
+            // Find which entry points touch this variable
+            let mut stages = wgpu::ShaderStages::NONE;
+            for (index, entry_point) in module.entry_points.iter().enumerate(){
+                if !info.get_entry_point(index)[handle].is_empty(){
+                    stages |= match entry_point.stage{
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    };
+                }
+            }
+
+            bindings.push(ReflectedBinding{
+                group: binding.group,
+                binding: binding.binding,
+                name: variable.name.clone().unwrap_or_default(),
+                kind,
+                stages,
+            });
+        }
+
+        bindings.sort_by_key(|b| (b.group, b.binding));
+
+        Self{
+            bindings,
+        }
+    }
+
+    pub fn get(&self, group: u32, binding: u32) -> Option<&ReflectedBinding>{
+        self.bindings.iter().find(|b| b.group == group && b.binding == binding)
+    }
+
+    /// The highest bind group index the shader uses, if any.
+    pub fn max_group(&self) -> Option<u32>{
+        self.bindings.iter().map(|b| b.group).max()
+    }
+
+    /// Check the node's resources against the shader. Every binding the shader
+    /// uses must be provided with a compatible type, size and visibility.
+    pub fn check_bindings(&self, provided: &[ProvidedBinding]) -> Vec<BindingMismatch>{
+        let mut mismatches = Vec::new();
+
+        for expected in self.bindings.iter(){
+            // Bindings that no entry point uses don't need to be bound
+            if expected.stages.is_empty(){
+                continue;
+            }
+
+            let mismatch = |message: String| BindingMismatch{
+                group: expected.group,
+                binding: expected.binding,
+                name: Some(expected.name.clone()),
+                message,
+            };
+
+            let Some(resource) = provided.iter().find(|p| p.group == expected.group && p.binding == expected.binding) else {
+                mismatches.push(mismatch(format!("shader expects a {} but the node provides nothing", expected.kind)));
+                continue;
+            };
+
+            if let Err(message) = expected.kind.check(&resource.kind){
+                mismatches.push(mismatch(message));
+                continue;
+            }
+
+            if !resource.visibility.contains(expected.stages){
+                mismatches.push(mismatch(format!("used in {:?} but only visible to {:?}", expected.stages, resource.visibility)));
+            }
+        }
+
+        mismatches
+    }
+
+    fn binding_kind(module: &naga::Module, variable: &naga::GlobalVariable) -> Option<BindingKind>{
+        let ty = &module.types[variable.ty];
+        let size = ty.inner.size(module.to_ctx()) as u64;
+
+        match variable.space{
+            naga::AddressSpace::Uniform => Some(BindingKind::UniformBuffer{ size }),
+            naga::AddressSpace::Storage { access } => Some(BindingKind::StorageBuffer{
+                size,
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            }),
+            naga::AddressSpace::Handle => match ty.inner{
+                naga::TypeInner::Image { dim, arrayed, class } => {
+                    let view_dimension = Self::view_dimension(dim, arrayed);
+                    match class{
+                        naga::ImageClass::Sampled { kind, multi } => Some(BindingKind::Texture{
+                            sample_type: match kind{
+                                naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                                naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                                _ => wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            view_dimension,
+                            multisampled: multi,
+                        }),
+                        naga::ImageClass::Depth { multi } => Some(BindingKind::Texture{
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension,
+                            multisampled: multi,
+                        }),
+                        naga::ImageClass::Storage { .. } => Some(BindingKind::StorageTexture{ view_dimension }),
+                    }
+                }
+                naga::TypeInner::Sampler { comparison } => Some(BindingKind::Sampler{ comparison }),
+                naga::TypeInner::AccelerationStructure => Some(BindingKind::AccelerationStructure),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn view_dimension(dim: naga::ImageDimension, arrayed: bool) -> wgpu::TextureViewDimension{
+        match (dim, arrayed){
+            (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+            (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+            (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+            (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+            (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+            (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn reflect(source: &str) -> ShaderReflection{
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .unwrap();
+        ShaderReflection::new(&module, &info)
+    }
+
+    fn provided(group: u32, binding: u32, ty: wgpu::BindingType) -> ProvidedBinding{
+        ProvidedBinding::from_layout_entry(group, &wgpu::BindGroupLayoutEntry{
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty,
+            count: None,
+        })
+    }
+
+    #[test]
+    fn acceleration_structures_are_checked_like_other_bindings(){
+        let reflection = reflect("
+            @group(0) @binding(0) var scene: acceleration_structure;
+            @fragment fn main() -> @location(0) vec4<f32> {
+                var query: ray_query;
+                rayQueryInitialize(&query, scene, RayDesc(0u, 0xFFu, 0.0, 1.0, vec3<f32>(0.0), vec3<f32>(0.0, 0.0, 1.0)));
+                return vec4<f32>(1.0);
+            }
+        ");
+        assert_eq!(reflection.get(0, 0).unwrap().kind, BindingKind::AccelerationStructure);
+
+        assert!(reflection.check_bindings(&[provided(0, 0, wgpu::BindingType::AccelerationStructure)]).is_empty());
+
+        let mismatches = reflection.check_bindings(&[provided(0, 0, wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering))]);
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].message.contains("acceleration structure"));
+    }
+}
@@ -0,0 +1,74 @@
+use super::ShaderPreprocessor;
+
+/// A Rust type with a matching WGSL struct declaration, usually from
+/// `#[derive(Uniform)]` with `#[uniform(wgsl)]`.
+///
+/// Register it with `ShaderPreprocessor::add_struct` (or `Renderer::add_shader_struct`)
+/// and shaders can `#include "<WGSL_NAME>.wgsl"` instead of redeclaring it by hand.
+pub trait WgslStruct{
+    const WGSL_NAME: &'static str;
+
+    fn wgsl_struct() -> String;
+
+    /// Add the declaration to `preprocessor` as `<WGSL_NAME>.wgsl`. The derive also
+    /// registers every struct the declaration includes, so nested structs resolve.
+    fn register(preprocessor: &mut ShaderPreprocessor){
+        preprocessor.add_source(format!("{}.wgsl", Self::WGSL_NAME), Self::wgsl_struct());
+    }
+}
+
+// encase's ShaderType derive emits a `check` function per field that nothing calls
+#[cfg(test)]
+#[allow(dead_code)]
+mod tests{
+    use encase::ShaderType;
+    use wgpu::naga;
+
+    use super::*;
+    use crate::Uniform;
+
+    #[derive(ShaderType, Uniform)]
+    #[uniform(wgsl)]
+    struct Light{
+        color: nalgebra::Vector3<f32>,
+        intensity: f32,
+    }
+
+    #[derive(ShaderType, Uniform)]
+    #[uniform(wgsl = "Lighting")]
+    struct LightingUniform{
+        lights: [Light; 4],
+        count: u32,
+    }
+
+    #[test]
+    fn derived_declarations(){
+        assert_eq!(Light::WGSL_NAME, "Light");
+        assert!(Light::wgsl_struct().contains("struct Light {\n    color: vec3<f32>,\n    intensity: f32,\n};"));
+
+        let lighting = LightingUniform::wgsl_struct();
+        assert!(lighting.contains("#include \"Light.wgsl\""));
+        assert!(lighting.contains("lights: array<Light, 4>,"));
+    }
+
+    #[test]
+    fn registering_a_struct_registers_what_it_includes(){
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.add_struct::<LightingUniform>();
+
+        let source = preprocessor.process_str("\
+            #include \"Lighting.wgsl\"
+            @group(0) @binding(0) var<uniform> lighting: Lighting;
+            @fragment fn main() -> @location(0) vec4<f32> {
+                return vec4<f32>(lighting.lights[0].color * lighting.lights[0].intensity, 1.0);
+            }", ".").unwrap();
+
+        let module = naga::front::wgsl::parse_str(&source).unwrap();
+        let lighting = module.types.iter().find(|(_, ty)| ty.name.as_deref() == Some("Lighting")).unwrap().1;
+
+        // The WGSL struct lays out like the Rust one
+        assert_eq!(lighting.inner.size(module.to_ctx()) as u64, LightingUniform::min_size().get());
+        assert_eq!(LightingUniform{ lights: std::array::from_fn(|_| Light{ color: nalgebra::Vector3::zeros(), intensity: 0.0 }), count: 0 }
+                       .to_wgpu().len() as u64, LightingUniform::min_size().get());
+    }
+}
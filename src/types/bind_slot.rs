@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a resource is bound in the shader: `@group(group) @binding(binding)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BindSlot{
+    pub group: u32,
+    pub binding: u32,
+}
+
+impl BindSlot{
+    pub fn new(group: u32, binding: u32) -> Self{
+        Self{
+            group,
+            binding,
+        }
+    }
+}
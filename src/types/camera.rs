@@ -1,4 +1,5 @@
-use encase::{ShaderType, UniformBuffer};
+use encase::ShaderType;
+use minirender_derive::Uniform;
 use log::info;
 
 use crate::MutHandle;
@@ -43,9 +44,7 @@ impl Camera{
     }
 
     pub(crate) fn to_wgpu_bytes(&self) -> Vec<u8>{
-        let mut buffer = UniformBuffer::new(Vec::new());
-        buffer.write(&CameraUniform::new(self)).unwrap();
-        buffer.into_inner()
+        CameraUniform::new(self).to_wgpu()
     }
 
     pub fn update(&mut self){
@@ -112,9 +111,10 @@ impl Uniform for Camera{
     }
 }
 
-#[derive(Debug, Clone, Copy, ShaderType)]
+#[derive(Debug, Clone, Copy, ShaderType, Uniform)]
+#[uniform(wgsl = "Camera")]
 pub struct CameraUniform{
-    view_proj: nalgebra::Matrix4<f32>,
+    proj_view: nalgebra::Matrix4<f32>,
 }
 
 impl CameraUniform{
@@ -123,10 +123,8 @@ impl CameraUniform{
 
         let proj = nalgebra::Perspective3::new(camera.aspect, camera.fov.to_radians(), camera.near, camera.far).into_inner();
 
-        let view_proj = proj * view;
-
         Self{
-            view_proj,
+            proj_view: proj * view,
         }
     }
 }
@@ -0,0 +1,26 @@
+// wgpu's layouts for indirect draw arguments: DrawIndirectArgs for Command::DrawIndirect,
+// DrawIndexedIndirectArgs for Command::DrawIndexedIndirect and MultiDrawIndexedIndirect.
+// Store them in a buffer from Renderer::create_indirect_buffer, or fill one from a
+// compute shader.
+pub use wgpu::util::{DrawIndexedIndirectArgs, DrawIndirectArgs};
+
+/// Indirect draw arguments that a storage buffer can hold. wgpu's argument structs
+/// aren't `bytemuck::Pod`, so they get their own way into a `StorageBuffer`.
+pub trait IndirectArgs: Copy + 'static{
+    // Bytes per draw, the stride between draws in an args buffer
+    const SIZE: u64 = std::mem::size_of::<Self>() as u64;
+
+    fn as_bytes(&self) -> &[u8];
+}
+
+impl IndirectArgs for DrawIndirectArgs{
+    fn as_bytes(&self) -> &[u8]{
+        DrawIndirectArgs::as_bytes(self)
+    }
+}
+
+impl IndirectArgs for DrawIndexedIndirectArgs{
+    fn as_bytes(&self) -> &[u8]{
+        DrawIndexedIndirectArgs::as_bytes(self)
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{Handle, MutHandle};
-use crate::pipeline::Pipeline;
+use crate::pipeline::{Pipeline, PipelineSettings};
 use crate::render_graph::{DrawCommand, ResourceHandle, ResourceManager};
 
 pub struct Material{
@@ -55,18 +55,26 @@ impl Material{
         }
     }
 
-    pub fn build_material(&mut self, device: Handle<wgpu::Device>, shader: wgpu::ShaderModule,
+    pub fn build_material(&mut self, device: Handle<wgpu::Device>, shader: &wgpu::ShaderModule,
                           bind_group_layouts: Vec<&wgpu::BindGroupLayout>,
-                          vertex_buffer_layouts: Vec<wgpu::VertexBufferLayout>, use_depth: bool){
+                          vertex_buffer_layouts: Vec<wgpu::VertexBufferLayout>, use_depth: bool) -> Result<(), wgpu::Error>{
+        let mut pipeline_settings = PipelineSettings::default();
+
+        if use_depth{
+            pipeline_settings = pipeline_settings.enable_depth_stencil();
+        }
+
         let pipeline = Pipeline::new(
             device,
             shader,
             bind_group_layouts,
             vertex_buffer_layouts,
-            use_depth
-        );
+            pipeline_settings
+        )?;
 
         self.pipeline = Some(pipeline);
+
+        Ok(())
     }
 
     pub fn bind_pipeline<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>){
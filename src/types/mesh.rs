@@ -1,12 +1,12 @@
-use crate::types::{Instance, Vertex};
-use wgpu::util::{DeviceExt, DrawIndirectArgs, DrawIndexedIndirectArgs};
+use crate::types::{IndirectArgs, Vertex};
+use wgpu::util::{DeviceExt, DrawIndexedIndirectArgs};
 use crate::Handle;
 
 use super::InstanceBuffer;
 
+// Meshes are shared by every node drawing them, so instances live with the caller
 pub struct Mesh {
     submeshes: Vec<Submesh>,
-    instance_buffer: Option<InstanceBuffer>
 }
 
 impl Mesh {
@@ -72,7 +72,6 @@ impl Mesh {
 
         Self {
             submeshes,
-            instance_buffer: None
         }
     }
 
@@ -82,27 +81,45 @@ impl Mesh {
 
         Self {
             submeshes: vec![submesh],
-            instance_buffer: None
         }
     }
 
-    pub fn set_instances(&mut self, device: &wgpu::Device, instance_buffer: InstanceBuffer) {
-        for submesh in self.submeshes.iter_mut(){
-            submesh.create_instanced_indirect_args(device, &instance_buffer);
-        }
-        self.instance_buffer = Some(instance_buffer);
-    }
-
     pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
         for submesh in self.submeshes.iter() {
             submesh.render(render_pass);
         }
     }
 
-    pub fn render_instanced<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>){
-        if let Some(ib) = &self.instance_buffer{
-            for submesh in self.submeshes.iter(){
-                submesh.render_instanced(render_pass, &ib);
+    // Draw every submesh once per instance in `instance_buffer`
+    pub fn render_instanced<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instance_buffer: &'a InstanceBuffer){
+        for submesh in self.submeshes.iter(){
+            submesh.render_instanced(render_pass, instance_buffer);
+        }
+    }
+
+    // How many submeshes the mesh draws, each with its own indexed draw
+    pub fn submesh_count(&self) -> usize {
+        self.submeshes.len()
+    }
+
+    // Draw every submesh with `count` DrawIndexedIndirectArgs each from `buffer`, starting at
+    // `offset`: the first submesh's args come first, then the next submesh's, and so on.
+    // `multi_draw` uses one multi_draw_indexed_indirect call per submesh (needs
+    // Features::MULTI_DRAW_INDIRECT), otherwise the draws are issued one by one.
+    pub fn render_indexed_indirect<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, buffer: &'a wgpu::Buffer,
+                                       offset: wgpu::BufferAddress, count: u32, multi_draw: bool){
+        let stride = DrawIndexedIndirectArgs::SIZE * count as u64;
+
+        for (i, submesh) in self.submeshes.iter().enumerate(){
+            submesh.bind(render_pass);
+
+            let offset = offset + stride * i as u64;
+            if multi_draw{
+                render_pass.multi_draw_indexed_indirect(buffer, offset, count);
+            }else{
+                for draw in 0..count as u64{
+                    render_pass.draw_indexed_indirect(buffer, offset + draw * DrawIndexedIndirectArgs::SIZE);
+                }
             }
         }
     }
@@ -112,7 +129,6 @@ pub struct Submesh {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
-    indirect_args: Option<wgpu::Buffer>,
 }
 
 impl Submesh {
@@ -133,50 +149,22 @@ impl Submesh {
             vertex_buffer,
             index_buffer,
             num_indices: indices.len() as u32,
-            indirect_args: None,
         }
     }
 
-    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        self.bind(render_pass);
         render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
     }
 
     pub fn render_instanced<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instance_buffer: &'a InstanceBuffer){
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.bind(render_pass);
         instance_buffer.bind_as_buffer(1, render_pass);
-
-        if let Some(indirect_args) = &self.indirect_args{
-            render_pass.draw_indexed_indirect(indirect_args, 0);
-        }else{
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..instance_buffer.get_instance_count() as u32);
-        }
-    }
-
-    fn create_instanced_indirect_args(&mut self, device: &wgpu::Device, instance_buffer: &InstanceBuffer){
-        let indirect_args = DrawIndexedIndirectArgs {
-            index_count: self.num_indices as u32,
-            instance_count: instance_buffer.get_instance_count() as u32,
-            first_index: 0,
-            base_vertex: 0,
-            first_instance: 0,
-        };
-
-        // cast to u8; note this is not a bytemuck cast as the struct is not repr(C)
-        let indirect_args_data = {
-            let indirect_args_ptr = &indirect_args as *const DrawIndexedIndirectArgs as *const u8;
-            unsafe { std::slice::from_raw_parts(indirect_args_ptr, std::mem::size_of::<DrawIndexedIndirectArgs>()) }
-        };
-        
-
-        let indirect_args_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Indirect Args Buffer"),
-            contents: indirect_args_data,
-            usage: wgpu::BufferUsages::INDIRECT,
-        });
-
-        self.indirect_args = Some(indirect_args_buffer);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..instance_buffer.get_instance_count() as u32);
     }
 }
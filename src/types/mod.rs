@@ -1,21 +1,31 @@
+mod bind_slot;
 mod camera;
 mod instance_buffer;
 mod mesh;
 mod material;
 mod texture;
+mod render_target;
 mod vertex;
 mod uniform_buffer;
 mod uniform_set;
+mod uniform_arena;
+mod storage_buffer;
+mod indirect_args;
 mod transform;
 mod render_object;
 
-pub use camera::Camera;
+pub use bind_slot::BindSlot;
+pub use camera::{Camera, CameraUniform};
 pub use instance_buffer::InstanceBuffer;
 pub use mesh::Mesh;
 pub use material::Material;
 pub use texture::Texture;
-pub use transform::Transform;
-pub use uniform_buffer::{UniformBuffer, Uniform};
-pub use uniform_set::{UniformBufferType, UniformSet};
+pub use render_target::RenderTarget;
+pub use transform::{Transform, TransformHandle, TransformUniform};
+pub use uniform_buffer::{RawUniformBuffer, UniformBuffer, Uniform};
+pub use uniform_set::UniformSet;
+pub use uniform_arena::UniformArena;
+pub use storage_buffer::{RawStorageBuffer, StorageBuffer};
+pub use indirect_args::{DrawIndexedIndirectArgs, DrawIndirectArgs, IndirectArgs};
 pub use vertex::{Vertex, Instance};
 
@@ -0,0 +1,63 @@
+use crate::MutHandle;
+
+// A color attachment that's only ever rendered to, like the multisampled targets
+// MSAA nodes draw into before resolving. Sized to match the surface.
+pub struct RenderTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+
+    size: wgpu::Extent3d,
+}
+
+impl RenderTarget {
+    pub fn new(device: &wgpu::Device, sc_desc: MutHandle<wgpu::SurfaceConfiguration>, sample_count: u32) -> Self {
+        let sc_desc = sc_desc.lock().unwrap();
+
+        let size = wgpu::Extent3d {
+            width: sc_desc.width,
+            height: sc_desc.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = Self::create_texture(device, size, sc_desc.format, sample_count);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+
+            size,
+        }
+    }
+
+    fn create_texture(device: &wgpu::Device, size: wgpu::Extent3d, format: wgpu::TextureFormat, sample_count: u32) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label: Some("Render Target"),
+            view_formats: &[],
+        })
+    }
+
+    pub fn get_texture_size(&self) -> wgpu::Extent3d {
+        self.size
+    }
+
+    // Recreate the target if the surface changed size (or format). The contents are lost.
+    pub fn resize(&mut self, device: &wgpu::Device, sc_desc: MutHandle<wgpu::SurfaceConfiguration>) {
+        let sc_desc = sc_desc.lock().unwrap();
+
+        self.size = wgpu::Extent3d {
+            width: sc_desc.width,
+            height: sc_desc.height,
+            depth_or_array_layers: 1,
+        };
+
+        self.texture = Self::create_texture(device, self.size, sc_desc.format, self.texture.sample_count());
+        self.view = self.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    }
+}
@@ -0,0 +1,153 @@
+use std::marker::PhantomData;
+
+use wgpu::util::DeviceExt;
+
+use crate::Handle;
+use crate::types::IndirectArgs;
+
+// The untyped side of a storage buffer - what the ResourceManager keeps and
+// render nodes bind
+pub struct RawStorageBuffer{
+    pub(crate) buffer: wgpu::Buffer,
+    pub(crate) read_only: bool,
+    visibility: wgpu::ShaderStages,
+}
+
+impl RawStorageBuffer{
+    pub fn layout_entry(&self, binding: u32) -> wgpu::BindGroupLayoutEntry{
+        wgpu::BindGroupLayoutEntry{
+            binding,
+            visibility: self.visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: self.read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
+
+/// A typed handle to a storage buffer holding an array of `T`, for data too big
+/// for a uniform (lights, bones, per-instance data, ...). Storage buffers can also
+/// hold indirect draw arguments, so a compute pass can decide what gets drawn.
+///
+/// `T` is copied byte for byte, so it must match the WGSL struct's layout
+/// (including any padding) and be a multiple of 4 bytes. That's any `bytemuck::Pod`
+/// type, or one of wgpu's indirect argument structs (see IndirectArgs).
+pub struct StorageBuffer<T>{
+    raw: Handle<RawStorageBuffer>,
+    len: usize,
+
+    _queue: Handle<wgpu::Queue>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for StorageBuffer<T>{
+    fn clone(&self) -> Self{
+        Self{
+            raw: self.raw.clone(),
+            len: self.len,
+
+            _queue: self._queue.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> StorageBuffer<T>{
+    fn from_bytes(device: &wgpu::Device, queue: Handle<wgpu::Queue>, mut contents: Vec<u8>, len: usize, read_only: bool) -> Self{
+        // Bindings can't be empty, so always leave room for one element
+        contents.resize(contents.len().max(std::mem::size_of::<T>()), 0);
+
+        let buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor{
+                label: Some("Storage Buffer"),
+                contents: &contents,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        // Vertex shaders can't write to storage buffers without an extra feature
+        let visibility = if read_only || device.features().contains(wgpu::Features::VERTEX_WRITABLE_STORAGE) {
+            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT
+        } else {
+            wgpu::ShaderStages::FRAGMENT
+        };
+
+        Self{
+            raw: Handle::new(RawStorageBuffer{
+                buffer,
+                read_only,
+                visibility,
+            }),
+            len,
+
+            _queue: queue,
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn get_raw(&self) -> Handle<RawStorageBuffer>{
+        self.raw.clone()
+    }
+
+    // How many elements the buffer holds
+    pub fn len(&self) -> usize{
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.len == 0
+    }
+
+    pub fn is_read_only(&self) -> bool{
+        self.raw.read_only
+    }
+
+    // Write `count` elements' bytes starting at element `first`
+    fn write_bytes(&self, first: usize, count: usize, bytes: &[u8]){
+        if first + count > self.len{
+            panic!("Storage buffer overflow: writing elements {}..{} of a {} element buffer", first, first + count, self.len);
+        }
+
+        let offset = (first * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+        self._queue.write_buffer(&self.raw.buffer, offset, bytes);
+    }
+}
+
+impl<T: bytemuck::Pod> StorageBuffer<T>{
+    pub(crate) fn new(device: &wgpu::Device, queue: Handle<wgpu::Queue>, data: &[T], read_only: bool) -> Self{
+        Self::from_bytes(device, queue, bytemuck::cast_slice::<T, u8>(data).to_vec(), data.len(), read_only)
+    }
+
+    // Overwrite the buffer from the first element
+    pub fn update(&self, data: &[T]){
+        self.update_range(0, data);
+    }
+
+    // Overwrite `data.len()` elements starting at element `first`
+    pub fn update_range(&self, first: usize, data: &[T]){
+        self.write_bytes(first, data.len(), bytemuck::cast_slice(data));
+    }
+}
+
+impl<T: IndirectArgs> StorageBuffer<T>{
+    pub(crate) fn new_indirect(device: &wgpu::Device, queue: Handle<wgpu::Queue>, args: &[T], read_only: bool) -> Self{
+        Self::from_bytes(device, queue, Self::args_bytes(args), args.len(), read_only)
+    }
+
+    // Overwrite the buffer's draw arguments from the first draw
+    pub fn update_args(&self, args: &[T]){
+        self.update_args_range(0, args);
+    }
+
+    // Overwrite `args.len()` draws' arguments starting at draw `first`
+    pub fn update_args_range(&self, first: usize, args: &[T]){
+        self.write_bytes(first, args.len(), &Self::args_bytes(args));
+    }
+
+    fn args_bytes(args: &[T]) -> Vec<u8>{
+        args.iter().flat_map(|args| args.as_bytes()).copied().collect()
+    }
+}
@@ -9,6 +9,8 @@ pub struct Texture {
 
     bind_group: wgpu::BindGroup,
     bind_group_layout: wgpu::BindGroupLayout,
+    // Kept so render nodes can check the layout against their shader
+    bind_group_layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
 }
 
 impl Texture {
@@ -20,6 +22,10 @@ impl Texture {
         &self.bind_group_layout
     }
 
+    pub fn get_bind_group_layout_entries(&self) -> &[wgpu::BindGroupLayoutEntry] {
+        &self.bind_group_layout_entries
+    }
+
     pub fn get_texture_size(&self) -> wgpu::Extent3d {
         self.size
     }
@@ -83,25 +89,27 @@ impl Texture {
             label: Some("Texture Sampler"),
         });
 
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
+        let bind_group_layout_entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
                 },
-            ],
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &bind_group_layout_entries,
             label: Some("Texture Bind Group Layout"),
         });
 
@@ -129,10 +137,12 @@ impl Texture {
 
             bind_group,
             bind_group_layout,
+            bind_group_layout_entries,
         }
     }
 
-    pub fn create_depth_texture(device: &wgpu::Device, sc_desc: MutHandle<wgpu::SurfaceConfiguration>) -> Self {
+    pub fn create_depth_texture(device: &wgpu::Device, sc_desc: MutHandle<wgpu::SurfaceConfiguration>,
+                                format: wgpu::TextureFormat, sample_count: u32) -> Self {
         let sc_desc = sc_desc.lock().unwrap();
 
         let size = wgpu::Extent3d {
@@ -144,15 +154,21 @@ impl Texture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             label: Some("Depth Texture"),
             view_formats: &[],
         });
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Shaders can only sample the depth aspect of combined depth/stencil formats
+        let depth_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -168,25 +184,27 @@ impl Texture {
             label: Some("Depth Texture Sampler"),
         });
 
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Depth,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
+        let bind_group_layout_entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: sample_count > 1,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
                 },
-            ],
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &bind_group_layout_entries,
             label: Some("Depth Texture Bind Group Layout")
         });
 
@@ -195,7 +213,7 @@ impl Texture {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&view),
+                    resource: wgpu::BindingResource::TextureView(&depth_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
@@ -213,7 +231,8 @@ impl Texture {
             size,
 
             bind_group,
-            bind_group_layout
+            bind_group_layout,
+            bind_group_layout_entries,
         }
     }
 
@@ -222,7 +241,7 @@ impl Texture {
     // Can be used for depth/full screen effects
     //
     // It is assumed screen textures do not store any data
-    // and are instead written to.
+    // and are instead written to. The format and sample count are kept.
     pub fn resize_screen_texture(&mut self, device: &wgpu::Device, sc_desc: MutHandle<wgpu::SurfaceConfiguration>) {
         let sc_desc = sc_desc.lock().unwrap();
 
@@ -235,11 +254,11 @@ impl Texture {
         self.texture = device.create_texture(&wgpu::TextureDescriptor {
             size: self.size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count: self.texture.sample_count(),
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            label: Some("Depth Texture"),
+            format: self.texture.format(),
+            usage: self.texture.usage(),
+            label: Some("Screen Texture"),
             view_formats: &[],
         });
 
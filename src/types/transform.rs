@@ -1,6 +1,8 @@
 use encase::ShaderType;
+use minirender_derive::Uniform;
 
 use crate::types::Instance;
+use crate::MutHandle;
 
 use super::Uniform;
 
@@ -35,23 +37,45 @@ impl Transform{
     }
 }
 
+// A transform shared between the caller and a DrawMesh command, so the object
+// can be moved after the graph is built. Read every frame when the node draws.
+#[derive(Clone)]
+pub struct TransformHandle{
+    inner: MutHandle<Transform>,
+}
+
+impl TransformHandle{
+    pub fn new(transform: Transform) -> Self{
+        Self{
+            inner: MutHandle::new(transform),
+        }
+    }
+
+    pub fn get(&self) -> Transform{
+        *self.inner.lock().unwrap()
+    }
+
+    pub fn set(&self, transform: Transform){
+        *self.inner.lock().unwrap() = transform;
+    }
+}
+
 impl Uniform for Transform{
     fn to_wgpu(&self) -> Vec<u8>{
-        let mut buffer = encase::UniformBuffer::new(Vec::new());
-        buffer.write(&TransformUniform::new(self)).unwrap();
-        buffer.into_inner()
+        TransformUniform::new(self).to_wgpu()
     }
 }
 
-#[derive(Debug, Clone, Copy, ShaderType)]
+#[derive(Debug, Clone, Copy, ShaderType, Uniform)]
+#[uniform(wgsl = "Transform")]
 pub struct TransformUniform{
-    pub model: nalgebra::Matrix4<f32>,
+    pub model_matrix: nalgebra::Matrix4<f32>,
 }
 
 impl TransformUniform{
     pub fn new(transform: &Transform) -> Self{
         Self{
-            model: transform.to_matrix().into(),
+            model_matrix: transform.to_matrix().into(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,168 @@
+use std::num::NonZeroU64;
+
+use crate::Handle;
+
+// One large uniform buffer holding a block of per-draw data (a model matrix,
+// material params, ...) for every draw in a node. Each block is bound with a
+// dynamic offset, so many draws share one bind group.
+//
+// The arena is refilled every frame: `reset`, `push` a block per draw, then `flush`.
+// Only a frame whose blocks differ from the last one's is uploaded.
+pub struct UniformArena{
+    buffer: wgpu::Buffer,
+    // The blocks as last pushed, and how far this frame's pushes have got
+    data: Vec<u8>,
+    len: usize,
+    dirty: bool,
+
+    binding: u32,
+    block_size: u64, // The binding size the shader sees
+    stride: u64,     // block_size rounded up to the device's offset alignment
+    capacity: u64,   // In blocks
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+
+    _device: Handle<wgpu::Device>,
+    _queue: Handle<wgpu::Queue>,
+}
+
+impl UniformArena{
+    pub fn new(device: Handle<wgpu::Device>, queue: Handle<wgpu::Queue>, binding: u32, block_size: u64, capacity: u64) -> Self{
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = block_size.div_ceil(alignment) * alignment;
+        let capacity = capacity.max(1);
+
+        let bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor{
+                label: Some("uniform_arena_bind_group_layout"),
+                entries: &[Self::layout_entry(binding, block_size)],
+            }
+        );
+
+        let buffer = Self::create_buffer(&device, stride * capacity);
+        let bind_group = Self::create_bind_group(&device, &bind_group_layout, &buffer, binding, block_size);
+
+        Self{
+            buffer,
+            data: Vec::new(),
+            len: 0,
+            dirty: false,
+
+            binding,
+            block_size,
+            stride,
+            capacity,
+
+            bind_group_layout,
+            bind_group,
+
+            _device: device,
+            _queue: queue,
+        }
+    }
+
+    fn layout_entry(binding: u32, block_size: u64) -> wgpu::BindGroupLayoutEntry{
+        wgpu::BindGroupLayoutEntry{
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: NonZeroU64::new(block_size),
+            },
+            count: None
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer{
+        device.create_buffer(&wgpu::BufferDescriptor{
+            label: Some("Uniform Arena Buffer"),
+            size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &wgpu::Buffer,
+                         binding: u32, block_size: u64) -> wgpu::BindGroup{
+        device.create_bind_group(&wgpu::BindGroupDescriptor{
+            label: Some("uniform_arena_bind_group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry{
+                binding,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding{
+                    buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(block_size),
+                }),
+            }],
+        })
+    }
+
+    pub fn layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry>{
+        vec![Self::layout_entry(self.binding, self.block_size)]
+    }
+
+    pub fn get_bind_group_layout(&self) -> &wgpu::BindGroupLayout{
+        &self.bind_group_layout
+    }
+
+    pub fn block_size(&self) -> u64{
+        self.block_size
+    }
+
+    // Start a new frame
+    pub fn reset(&mut self){
+        self.len = 0;
+    }
+
+    // Append a block, returning the dynamic offset to bind it with, or None if it's
+    // bigger than the arena's blocks (data behind a MutHandle can grow after the build)
+    pub fn push(&mut self, block: &[u8]) -> Option<u32>{
+        if block.len() as u64 > self.block_size{
+            return None;
+        }
+
+        let offset = self.len;
+        let end = offset + self.stride as usize;
+        if self.data.len() < end{
+            self.data.resize(end, 0);
+            self.dirty = true;
+        }
+
+        let slot = &mut self.data[offset..end];
+        let (head, tail) = slot.split_at_mut(block.len());
+        if head != block || tail.iter().any(|byte| *byte != 0){
+            head.copy_from_slice(block);
+            tail.fill(0);
+            self.dirty = true;
+        }
+
+        self.len = end;
+        Some(offset as u32)
+    }
+
+    // Upload this frame's blocks if they changed, growing the buffer if they don't fit
+    pub fn flush(&mut self){
+        self.data.truncate(self.len);
+        let blocks = self.data.len() as u64 / self.stride;
+
+        if blocks > self.capacity{
+            self.capacity = blocks.next_power_of_two();
+            self.buffer = Self::create_buffer(&self._device, self.stride * self.capacity);
+            self.bind_group = Self::create_bind_group(&self._device, &self.bind_group_layout, &self.buffer,
+                                                      self.binding, self.block_size);
+            self.dirty = true;
+        }
+
+        if self.dirty && !self.data.is_empty(){
+            self._queue.write_buffer(&self.buffer, 0, &self.data);
+        }
+        self.dirty = false;
+    }
+
+    pub fn bind<'a>(&'a self, index: u32, offset: u32, render_pass: &mut wgpu::RenderPass<'a>){
+        render_pass.set_bind_group(index, &self.bind_group, &[offset]);
+    }
+}
@@ -1,49 +1,112 @@
-use encase::{internal::WriteInto, ShaderType};
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
 use wgpu::util::DeviceExt;
-use crate::Handle;
+use crate::{Handle, MutHandle};
 
 pub trait Uniform{
     fn to_wgpu(&self) -> Vec<u8>;
 }
 
-pub struct UniformBuffer{
-    pub(crate) buffer: wgpu::Buffer,
-    pub(crate) size: usize, // Size of the buffer in bytes (used for updating the buffer
+// Shared uniform data, read again whenever it's uploaded - so a PushUniform block
+// can be changed through the handle without rebuilding the node
+impl<T: Uniform> Uniform for MutHandle<T>{
+    fn to_wgpu(&self) -> Vec<u8>{
+        self.lock().unwrap().to_wgpu()
+    }
+}
+
+// The untyped side of a uniform buffer - what uniform sets bind. The buffer is
+// swapped for a bigger one when the data outgrows it, and the sets notice the
+// new buffer and rebuild their bind groups.
+pub struct RawUniformBuffer{
+    buffer: Mutex<wgpu::Buffer>,
 
     _device: Handle<wgpu::Device>,
     _queue: Handle<wgpu::Queue>,
 }
 
-impl UniformBuffer{
-    pub(crate) fn new<T: Uniform>(device: Handle<wgpu::Device>, queue: Handle<wgpu::Queue>, data: &T) -> Self{
-        let data = data.to_wgpu();
+impl RawUniformBuffer{
+    fn new(device: Handle<wgpu::Device>, queue: Handle<wgpu::Queue>, data: &[u8]) -> Self{
+        Self{
+            buffer: Mutex::new(Self::create_buffer(&device, data)),
+
+            _device: device,
+            _queue: queue,
+        }
+    }
 
-        let buffer = device.create_buffer_init(
+    fn create_buffer(device: &wgpu::Device, data: &[u8]) -> wgpu::Buffer{
+        device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor{
                 label: Some("Uniform Buffer"),
-                contents: &data,
+                contents: data,
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             }
-        );
+        )
+    }
 
-        let size = data.len();
+    // Size of the buffer in bytes
+    pub fn size(&self) -> u64{
+        self.buffer.lock().unwrap().size()
+    }
 
-        Self{
-            buffer,
-            size,
+    pub fn buffer(&self) -> std::sync::MutexGuard<'_, wgpu::Buffer>{
+        self.buffer.lock().unwrap()
+    }
 
-            _device: device,
-            _queue: queue,
+    fn write(&self, data: &[u8]){
+        let mut buffer = self.buffer.lock().unwrap();
+
+        // Grow to fit (runtime-sized arrays), otherwise write in place
+        if data.len() as u64 > buffer.size(){
+            *buffer = Self::create_buffer(&self._device, data);
+        }else if data.len() as u64 == buffer.size(){
+            self._queue.write_buffer(&buffer, 0, data);
+        }else{
+            // Zero the rest, so a shrunk array doesn't leave old elements behind it
+            let mut padded = data.to_vec();
+            padded.resize(buffer.size() as usize, 0);
+            self._queue.write_buffer(&buffer, 0, &padded);
         }
     }
+}
 
-    pub fn update<T: Uniform>(&self, data: &T){
-        let data = data.to_wgpu();
+/// A uniform buffer holding a `T`. Only a `T` can be written to it, so a
+/// `Transform` can't end up in the camera's buffer.
+pub struct UniformBuffer<T: Uniform>{
+    raw: Handle<RawUniformBuffer>,
+    _marker: PhantomData<T>,
+}
 
-        if data.len() != self.size{
-            panic!("Uniform buffer size mismatch");
+impl<T: Uniform> Clone for UniformBuffer<T>{
+    fn clone(&self) -> Self{
+        Self{
+            raw: self.raw.clone(),
+            _marker: PhantomData,
         }
+    }
+}
 
-        self._queue.write_buffer(&self.buffer, 0, &data);
+impl<T: Uniform> UniformBuffer<T>{
+    pub(crate) fn new(device: Handle<wgpu::Device>, queue: Handle<wgpu::Queue>, data: &T) -> Self{
+        Self{
+            raw: Handle::new(RawUniformBuffer::new(device, queue, &data.to_wgpu())),
+            _marker: PhantomData,
+        }
     }
-}
\ No newline at end of file
+
+    pub(crate) fn get_raw(&self) -> Handle<RawUniformBuffer>{
+        self.raw.clone()
+    }
+
+    pub fn size(&self) -> u64{
+        self.raw.size()
+    }
+
+    // Write new data. If it no longer fits (a runtime-sized array grew) the buffer
+    // is reallocated, and every node using it rebinds it before the next frame.
+    pub fn update(&self, data: &T){
+        self.raw.write(&data.to_wgpu());
+    }
+}
@@ -1,91 +1,152 @@
 use std::num::NonZeroU64;
-use std::sync::Arc;
 use wgpu::{BindGroup, BindGroupLayout};
 use crate::{Handle};
-use crate::types::uniform_buffer::UniformBuffer;
-
-pub enum UniformBufferType{
-    STATIC, // Uniform buffer that is only updated once, upon creation
-    DYNAMIC, // Uniform buffer that is updated every frame
-}
+use crate::types::uniform_buffer::RawUniformBuffer;
 
+// All the uniform buffers in one bind group, each at an explicit binding index.
+// The layout and bind group are built lazily by `prepare`, and rebuilt whenever
+// the set changes or a buffer behind it is swapped out.
 pub struct UniformSet{
-    pub uniform_buffers: Vec<Handle<UniformBuffer>>,
-    pub bind_group_layout: wgpu::BindGroupLayout,
-    pub bind_group: wgpu::BindGroup,
+    pub uniform_buffers: Vec<(u32, Handle<RawUniformBuffer>)>,
+    bind_group_layout: Option<BindGroupLayout>,
+    bind_group: Option<BindGroup>,
+
+    // The layout entries and buffers the current layout/bind group were built from
+    layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    bound_buffers: Vec<(u32, wgpu::Id<wgpu::Buffer>)>,
+    dirty: bool,
 }
 
 impl UniformSet{
-    pub fn new(device: &wgpu::Device, uniform_buffers: Vec<Handle<UniformBuffer>>) -> Self{
-
-        let (bind_group_layout, bind_group) = Self::create_bind_groups(device, &uniform_buffers);
-
+    pub fn new(uniform_buffers: Vec<(u32, Handle<RawUniformBuffer>)>) -> Self{
         Self{
             uniform_buffers,
-            bind_group_layout,
-            bind_group,
+            bind_group_layout: None,
+            bind_group: None,
+
+            layout_entries: Vec::new(),
+            bound_buffers: Vec::new(),
+            dirty: true,
         }
     }
 
-    fn create_bind_groups<'a>(device: &'a wgpu::Device, uniform_buffers: &'a [Handle<UniformBuffer>]) -> (BindGroupLayout, BindGroup) {
-        let mut bind_group_layout_entries = Vec::new();
-        let mut bind_group_entries = Vec::new();
-
+    pub fn layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry>{
+        Self::create_layout_entries(&self.uniform_buffers)
+    }
 
-        for (i, uniform_buffer) in uniform_buffers.iter().enumerate(){
-            bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry{
-                binding: i as u32,
+    fn create_layout_entries(uniform_buffers: &[(u32, Handle<RawUniformBuffer>)]) -> Vec<wgpu::BindGroupLayoutEntry>{
+        uniform_buffers.iter().map(|(binding, uniform_buffer)| {
+            wgpu::BindGroupLayoutEntry{
+                binding: *binding,
                 visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
-                    min_binding_size: NonZeroU64::new(uniform_buffer.size as u64)
+                    min_binding_size: NonZeroU64::new(uniform_buffer.size())
                 },
                 count: None
-            });
-
-            bind_group_entries.push(wgpu::BindGroupEntry{
-                binding: i as u32,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding{
-                    buffer: &uniform_buffer.buffer,
-                    offset: 0,
-                    size: NonZeroU64::new(uniform_buffer.size as u64)
-                })
-            });
+            }
+        }).collect()
+    }
+
+    fn current_buffers(&self) -> Vec<(u32, wgpu::Id<wgpu::Buffer>)>{
+        self.uniform_buffers.iter().map(|(binding, uniform_buffer)| (*binding, uniform_buffer.buffer().global_id())).collect()
+    }
+
+    // Whether the bind group is missing or out of date
+    pub fn is_dirty(&self) -> bool{
+        self.dirty || self.bind_group.is_none() || self.bound_buffers != self.current_buffers()
+    }
+
+    // Rebuild the layout and bind group if needed. Returns true if the layout
+    // changed, in which case pipelines built against the old one must be rebuilt.
+    pub fn prepare(&mut self, device: &wgpu::Device) -> bool{
+        if !self.is_dirty(){
+            return false;
+        }
+
+        let layout_entries = self.layout_entries();
+        let layout_changed = self.bind_group_layout.is_none() || layout_entries != self.layout_entries;
+
+        if layout_changed{
+            self.bind_group_layout = Some(device.create_bind_group_layout(
+                &wgpu::BindGroupLayoutDescriptor{
+                    label: Some("uniform_set_bind_group_layout"),
+                    entries: &layout_entries
+                }
+            ));
+            self.layout_entries = layout_entries;
         }
 
-        let bind_group_layout = device.create_bind_group_layout(
-            &wgpu::BindGroupLayoutDescriptor{
-                label: Some("uniform_set_bind_group_layout"),
-                entries: &bind_group_layout_entries
+        // Hold the buffers while the bind group is created, they can be swapped out on
+        // resize. The same buffer can sit at several bindings, so each is locked once.
+        let mut distinct: Vec<&RawUniformBuffer> = Vec::new();
+        let buffer_indices: Vec<usize> = self.uniform_buffers.iter().map(|(_, uniform_buffer)| {
+            match distinct.iter().position(|buffer| std::ptr::eq(*buffer, &**uniform_buffer)){
+                Some(index) => index,
+                None => {
+                    distinct.push(uniform_buffer);
+                    distinct.len() - 1
+                }
             }
-        );
+        }).collect();
+        let guards: Vec<_> = distinct.iter().map(|buffer| buffer.buffer()).collect();
+
+        let buffers: Vec<(u32, &wgpu::Buffer)> = self.uniform_buffers.iter().zip(buffer_indices)
+            .map(|((binding, _), index)| (*binding, &*guards[index]))
+            .collect();
 
-        let bind_group = device.create_bind_group(
+        let bind_group_entries: Vec<wgpu::BindGroupEntry> = buffers.iter().map(|(binding, buffer)| {
+            wgpu::BindGroupEntry{
+                binding: *binding,
+                resource: buffer.as_entire_binding(),
+            }
+        }).collect();
+
+        self.bind_group = Some(device.create_bind_group(
             &wgpu::BindGroupDescriptor{
-                layout: &bind_group_layout,
+                layout: self.bind_group_layout.as_ref().unwrap(),
                 entries: &bind_group_entries,
                 label: Some("uniform_set_bind_group")
             }
-        );
+        ));
+
+        self.bound_buffers = buffers.iter().map(|(binding, buffer)| (*binding, buffer.global_id())).collect();
+        self.dirty = false;
 
-        (bind_group_layout, bind_group)
+        layout_changed
     }
 
-    pub fn add_uniform_buffer(&mut self, device: &wgpu::Device, uniform_buffer: UniformBuffer){
-        self.uniform_buffers.push(Handle::new(uniform_buffer));
+    pub fn has_binding(&self, binding: u32) -> bool{
+        self.uniform_buffers.iter().any(|(b, _)| *b == binding)
+    }
+
+    // Add a buffer at `binding`, replacing whatever was there. The bind group is
+    // rebuilt on the next `prepare`.
+    pub fn add_uniform_buffer(&mut self, binding: u32, uniform_buffer: Handle<RawUniformBuffer>){
+        self.uniform_buffers.retain(|(b, _)| *b != binding);
+        self.uniform_buffers.push((binding, uniform_buffer));
+        self.uniform_buffers.sort_by_key(|(b, _)| *b);
+
+        self.dirty = true;
+    }
 
-        let (bind_group_layout, bind_group) = Self::create_bind_groups(device, &self.uniform_buffers);
+    pub fn remove_uniform_buffer(&mut self, binding: u32){
+        let count = self.uniform_buffers.len();
+        self.uniform_buffers.retain(|(b, _)| *b != binding);
 
-        self.bind_group_layout = bind_group_layout;
-        self.bind_group = bind_group;
+        if self.uniform_buffers.len() != count{
+            self.dirty = true;
+        }
     }
 
-    pub fn add_existing_uniform_buffer(&mut self, uniform_buffer: Handle<UniformBuffer>){
-        self.uniform_buffers.push(uniform_buffer);
+    // The layout from the last `prepare`
+    pub fn get_bind_group_layout(&self) -> &wgpu::BindGroupLayout{
+        self.bind_group_layout.as_ref().expect("Uniform set has not been prepared")
     }
 
     pub fn bind<'a>(&'a self, index: u32, render_pass: &mut wgpu::RenderPass<'a>){
-        render_pass.set_bind_group(index, &self.bind_group, &[]);
+        let bind_group = self.bind_group.as_ref().expect("Uniform set has not been prepared");
+        render_pass.set_bind_group(index, bind_group, &[]);
     }
-}
\ No newline at end of file
+}
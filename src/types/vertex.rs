@@ -1,13 +1,19 @@
 
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub tex_coords: [f32; 2],
 }
 
+// Implemented by hand - bytemuck's derive leaves unused checks behind. Every field
+// is f32, so all that's needed is that repr(C) added no padding.
+const _: () = assert!(std::mem::size_of::<Vertex>() == std::mem::size_of::<[f32; 8]>());
+unsafe impl bytemuck::Zeroable for Vertex {}
+unsafe impl bytemuck::Pod for Vertex {}
+
 // lib.rs
 impl Vertex {
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -37,11 +43,15 @@ impl Vertex {
 
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Clone, Copy)]
 pub struct Instance{
     pub model: [[f32; 4]; 4],
 }
 
+const _: () = assert!(std::mem::size_of::<Instance>() == std::mem::size_of::<[f32; 16]>());
+unsafe impl bytemuck::Zeroable for Instance {}
+unsafe impl bytemuck::Pod for Instance {}
+
 impl Instance {
 pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {